@@ -0,0 +1,69 @@
+use crate::{
+    http::{Domain, ResponseDomain},
+    registry::Tld,
+    store::AuditStore,
+};
+use anyhow::{anyhow, Error};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+const REDACTED: [&str; 4] = ["secret_key", "key_id", "transfer", "history"];
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub(crate) struct AuditEntry {
+    pub(crate) at: i64,
+    pub(crate) actor: String,
+    pub(crate) action: String,
+    pub(crate) domain: String,
+    pub(crate) before: Option<Value>,
+    pub(crate) after: Option<Value>,
+}
+
+impl AuditEntry {
+    pub(crate) fn new(actor: &str, action: &str, before: Option<&Domain>, after: Option<&Domain>) -> Self {
+        let domain = after.or(before).map(|domain| format!("{}.{}", domain.name, domain.tld)).unwrap_or_default();
+
+        AuditEntry {
+            at: chrono::Utc::now().timestamp(),
+            actor: actor.to_string(),
+            action: action.to_string(),
+            domain,
+            before: before.and_then(snapshot),
+            after: after.and_then(snapshot),
+        }
+    }
+
+    pub(crate) fn tld(actor: &str, action: &str, before: Option<&Tld>, after: Option<&Tld>) -> Self {
+        AuditEntry {
+            at: chrono::Utc::now().timestamp(),
+            actor: actor.to_string(),
+            action: action.to_string(),
+            domain: after.or(before).map(|tld| format!(".{}", tld.name)).unwrap_or_default(),
+            before: before.and_then(|tld| serde_json::to_value(tld).ok()),
+            after: after.and_then(|tld| serde_json::to_value(tld).ok()),
+        }
+    }
+}
+
+pub(crate) fn snapshot(domain: &Domain) -> Option<Value> {
+    let mut value = serde_json::to_value(domain).ok()?;
+
+    if let Some(fields) = value.as_object_mut() {
+        REDACTED.iter().for_each(|field| drop(fields.remove(*field)));
+    }
+
+    Some(value)
+}
+
+/// The domain as `GET /domain/:name/:tld` shows it, for the change feed and webhooks. Banned domains are not public, so they have none.
+pub(crate) fn public(domain: &Domain) -> Option<Value> {
+    match domain.banned {
+        Some(_) => None,
+        None => serde_json::to_value(ResponseDomain::from(domain.clone())).ok(),
+    }
+}
+
+/// Writes the entry. Callers record before making the change, and give up on the change when this fails, so nothing goes unlogged.
+pub(crate) async fn record(store: &dyn AuditStore, entry: AuditEntry) -> Result<(), Error> {
+    store.append(&entry).await.map_err(|err| anyhow!("Failed to write audit entry for {} ({}): {err}", entry.domain, entry.action))
+}
@@ -0,0 +1,227 @@
+use crate::http::{Ban, Domain, DomainRecord, HistoryEntry, Subdomain};
+use crate::verification::Verification;
+use anyhow::{anyhow, Error};
+use serde::{Deserialize, Serialize};
+use std::{
+    fs::File,
+    io::{BufRead, BufReader, BufWriter, Write},
+    path::Path,
+};
+
+/// An entry read from a backup with its line or row number, or why it could not be read.
+pub type Entry = (usize, Result<Domain, Error>);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, clap::ValueEnum)]
+pub enum Format {
+    Jsonl,
+    Csv,
+}
+
+impl Format {
+    pub fn detect(path: &str, format: Option<Format>) -> Format {
+        format.unwrap_or_else(|| match Path::new(path).extension().and_then(|ext| ext.to_str()) {
+            Some(ext) if ext.eq_ignore_ascii_case("csv") => Format::Csv,
+            _ => Format::Jsonl,
+        })
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+struct Row {
+    name: String,
+    tld: String,
+    ip: String,
+    secret_key: Option<String>,
+    key_id: Option<String>,
+    recovery: Option<String>,
+    created_at: Option<i64>,
+    updated_at: Option<i64>,
+    expires_at: Option<i64>,
+    records: String,
+    subdomains: String,
+    // added later, so exports from before still read
+    #[serde(default)]
+    banned: String,
+    #[serde(default)]
+    verification: String,
+    #[serde(default)]
+    history: String,
+}
+
+impl Row {
+    fn from_domain(domain: &Domain) -> Result<Self, Error> {
+        Ok(Row {
+            name: domain.name.clone(),
+            tld: domain.tld.clone(),
+            ip: domain.ip.clone(),
+            secret_key: domain.secret_key.clone(),
+            key_id: domain.key_id.clone(),
+            recovery: domain.recovery.clone(),
+            created_at: domain.created_at,
+            updated_at: domain.updated_at,
+            expires_at: domain.expires_at,
+            records: serde_json::to_string(&domain.records)?,
+            subdomains: serde_json::to_string(&domain.subdomains)?,
+            banned: optional_json(&domain.banned)?,
+            verification: optional_json(&domain.verification)?,
+            history: serde_json::to_string(&domain.history)?,
+        })
+    }
+
+    fn into_domain(self) -> Result<Domain, Error> {
+        let records: Vec<DomainRecord> = if self.records.is_empty() { vec![] } else { serde_json::from_str(&self.records)? };
+        let subdomains: Vec<Subdomain> = if self.subdomains.is_empty() { vec![] } else { serde_json::from_str(&self.subdomains)? };
+        let banned: Option<Ban> = if self.banned.is_empty() { None } else { serde_json::from_str(&self.banned)? };
+        let verification: Option<Verification> = if self.verification.is_empty() { None } else { serde_json::from_str(&self.verification)? };
+        let history: Vec<HistoryEntry> = if self.history.is_empty() { vec![] } else { serde_json::from_str(&self.history)? };
+
+        Ok(Domain {
+            name: self.name.to_lowercase(),
+            tld: self.tld.to_lowercase(),
+            ip: self.ip,
+            secret_key: self.secret_key.filter(|key| !key.is_empty()),
+            key_id: self.key_id.filter(|id| !id.is_empty()),
+            recovery: self.recovery.filter(|contact| !contact.is_empty()),
+            created_at: self.created_at,
+            updated_at: self.updated_at,
+            expires_at: self.expires_at,
+            records,
+            subdomains,
+            banned,
+            verification,
+            history,
+            ..Default::default()
+        })
+    }
+}
+
+fn optional_json<T: Serialize>(value: &Option<T>) -> Result<String, Error> {
+    match value {
+        Some(value) => Ok(serde_json::to_string(value)?),
+        None => Ok(String::new()),
+    }
+}
+
+pub fn write(path: &str, format: Format, domains: &[Domain]) -> Result<(), Error> {
+    let file = File::create(path)?;
+
+    match format {
+        Format::Jsonl => {
+            let mut out = BufWriter::new(file);
+            for domain in domains {
+                serde_json::to_writer(&mut out, domain)?;
+                out.write_all(b"\n")?;
+            }
+            out.flush()?;
+        }
+        Format::Csv => {
+            let mut out = csv::Writer::from_writer(file);
+            for domain in domains {
+                out.serialize(Row::from_domain(domain)?)?;
+            }
+            out.flush()?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Reads every entry from `path`.
+pub fn read(path: &str, format: Format) -> Result<Vec<Entry>, Error> {
+    let file = File::open(path)?;
+
+    match format {
+        Format::Jsonl => Ok(BufReader::new(file)
+            .lines()
+            .enumerate()
+            .filter(|(_, line)| line.as_ref().map_or(true, |line| !line.trim().is_empty()))
+            .map(|(index, line)| (index + 1, line.map_err(Error::from).and_then(|line| Ok(serde_json::from_str(&line)?))))
+            .collect()),
+        Format::Csv => Ok(csv::Reader::from_reader(file)
+            .deserialize::<Row>()
+            .enumerate()
+            .map(|(index, row)| (index + 2, row.map_err(|err| anyhow!(err)).and_then(Row::into_domain)))
+            .collect()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::http::RecordKind;
+
+    fn temp(name: &str) -> String { std::env::temp_dir().join(format!("webx-backup-{}-{name}", std::process::id())).to_string_lossy().into_owned() }
+
+    fn domain() -> Domain {
+        Domain {
+            name: "bank".into(),
+            tld: "it".into(),
+            ip: "1.1.1.1".into(),
+            key_id: Some("k1".into()),
+            secret_key: Some("hash".into()),
+            recovery: Some("owner@example.com".into()),
+            created_at: Some(10),
+            updated_at: Some(20),
+            expires_at: Some(30),
+            records: vec![
+                DomainRecord::primary("1.1.1.1"),
+                DomainRecord {
+                    kind: RecordKind::TXT,
+                    value: "hello, \"world\"".into(),
+                    ..DomainRecord::primary("1.1.1.1")
+                },
+            ],
+            subdomains: vec![Subdomain { name: "api".into(), ip: "2.2.2.2".into() }],
+            banned: Some(Ban { reason: Some("spam".into()), at: 40 }),
+            verification: Some(Verification { token: "t".into(), issued_at: 50, target: Some("1.1.1.1".into()), verified_at: Some(60) }),
+            history: vec![HistoryEntry { event: "created".into(), at: 10 }],
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn exports_read_back_unchanged() {
+        for format in [Format::Jsonl, Format::Csv] {
+            let path = temp(&format!("{format:?}"));
+            write(&path, format, &[domain(), Domain { name: "shop".into(), ..Default::default() }]).unwrap();
+            let read = read(&path, format).unwrap();
+            std::fs::remove_file(&path).unwrap();
+
+            let domains: Vec<Domain> = read.into_iter().map(|(_, domain)| domain.unwrap()).collect();
+            assert_eq!(domains.len(), 2, "{format:?}");
+            assert_eq!(serde_json::to_value(&domains[0]).unwrap(), serde_json::to_value(domain()).unwrap(), "{format:?}");
+            assert_eq!(domains[1].name, "shop", "{format:?}");
+        }
+    }
+
+    #[test]
+    fn bad_entries_keep_their_line_numbers() {
+        let path = temp("bad.jsonl");
+        std::fs::write(&path, format!("{}\n\nnot json\n", serde_json::to_string(&domain()).unwrap())).unwrap();
+        let read = read(&path, Format::Jsonl).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(read.iter().map(|(line, domain)| (*line, domain.is_ok())).collect::<Vec<_>>(), [(1, true), (3, false)]);
+    }
+
+    #[test]
+    fn csv_exports_from_before_bans_were_carried_still_read() {
+        let path = temp("old.csv");
+        let csv = "name,tld,ip,secret_key,key_id,recovery,created_at,updated_at,expires_at,records,subdomains\nbank,IT,1.1.1.1,,,,,,,,\n";
+        std::fs::write(&path, csv).unwrap();
+        let read = read(&path, Format::Csv).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        let (row, domain) = &read[0];
+        let domain = domain.as_ref().unwrap();
+        assert_eq!((*row, domain.tld.as_str()), (2, "it"));
+        assert!(domain.banned.is_none() && domain.secret_key.is_none() && domain.records.is_empty());
+    }
+
+    #[test]
+    fn formats_are_detected_from_the_extension() {
+        assert_eq!(Format::detect("backup.CSV", None), Format::Csv);
+        assert_eq!(Format::detect("backup.jsonl", None), Format::Jsonl);
+        assert_eq!(Format::detect("backup.csv", Some(Format::Jsonl)), Format::Jsonl);
+    }
+}
@@ -0,0 +1,199 @@
+use crate::{
+    audit,
+    config::Config,
+    http::Domain,
+    store::{ChangeStore, Store},
+    webhook::Event,
+};
+use actix_web::rt::time;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::{sync::Arc, time::Duration};
+use tokio::sync::{broadcast, mpsc};
+
+const PAGE: u64 = 100;
+const HEARTBEAT: Duration = Duration::from_secs(15);
+const GAP_WAIT: i64 = 10;
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Retention {
+    #[serde(default = "default_keep")]
+    pub(crate) keep: u64,
+    #[serde(default = "default_interval")]
+    pub(crate) interval: u64,
+}
+
+impl Default for Retention {
+    fn default() -> Self {
+        Self {
+            keep: default_keep(),
+            interval: default_interval(),
+        }
+    }
+}
+
+fn default_keep() -> u64 { 100_000 }
+fn default_interval() -> u64 { 3600 }
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub(crate) struct Change {
+    pub(crate) seq: u64,
+    pub(crate) event: Event,
+    pub(crate) action: String,
+    pub(crate) domain: String,
+    pub(crate) at: i64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) after: Option<Value>,
+}
+
+pub(crate) enum Frame {
+    Change(Change),
+    Ping,
+}
+
+impl Frame {
+    pub(crate) fn sse(&self) -> String {
+        match self {
+            Frame::Change(change) => format!("id: {}\nevent: {}\ndata: {}\n\n", change.seq, change.event, serde_json::to_string(change).unwrap_or_default()),
+            Frame::Ping => ": ping\n\n".into(),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub(crate) struct Feed {
+    pub(crate) store: Arc<dyn ChangeStore>,
+    wake: broadcast::Sender<u64>,
+}
+
+impl Feed {
+    pub(crate) fn new(store: Arc<dyn ChangeStore>) -> Self { Feed { store, wake: broadcast::channel(64).0 } }
+
+    /// Appends the change to the feed and wakes followers. Failures are logged, never returned.
+    pub(crate) async fn publish(&self, event: Event, action: &str, before: Option<&Domain>, after: Option<&Domain>) {
+        let domain = after.or(before).map(|domain| format!("{}.{}", domain.name, domain.tld)).unwrap_or_default();
+        let change = Change {
+            seq: 0,
+            event,
+            action: action.to_string(),
+            domain: domain.clone(),
+            at: chrono::Utc::now().timestamp(),
+            after: after.and_then(audit::public),
+        };
+
+        match self.store.append_change(change).await {
+            Ok(seq) => drop(self.wake.send(seq)),
+            Err(err) => log::error!("Failed to record {event} change for {domain}: {err}"),
+        }
+    }
+}
+
+/// Prunes the feed down to the newest `keep` changes on an interval. A `keep` of 0 keeps every change.
+pub(crate) fn spawn(store: Store, config: &Config) {
+    let retention = config.changes().clone();
+
+    if retention.keep == 0 {
+        return;
+    }
+
+    actix_web::rt::spawn(async move {
+        let mut interval = time::interval(Duration::from_secs(retention.interval.max(60)));
+
+        loop {
+            interval.tick().await;
+            match store.outbox.feed.store.prune_changes(retention.keep).await {
+                Ok(0) => {}
+                Ok(pruned) => log::info!("Pruned {pruned} changes from the feed"),
+                Err(err) => log::error!("Change feed pruning failed: {err}"),
+            }
+        }
+    });
+}
+
+/// Cuts `changes` at the first missing seq after `after`. A seq is taken before its change is written, so a later change can
+/// land first; a gap is only skipped once the change after it is older than `GAP_WAIT`, as its writer has failed by then.
+pub(crate) fn contiguous(after: u64, changes: Vec<Change>, now: i64) -> Vec<Change> {
+    let mut next = after + 1;
+
+    changes
+        .into_iter()
+        .take_while(|change| {
+            let ready = change.seq == next || change.at <= now - GAP_WAIT;
+            next = change.seq + 1;
+            ready
+        })
+        .collect()
+}
+
+/// Sends every change after `last` to `tx`, then follows the feed until the receiver is dropped.
+/// Changes made by other processes, such as the CLI, are picked up on the next heartbeat.
+pub(crate) async fn follow(feed: Feed, mut last: u64, tx: mpsc::Sender<Frame>) {
+    let mut wake = feed.wake.subscribe();
+    let mut heartbeat = time::interval_at(time::Instant::now() + HEARTBEAT, HEARTBEAT);
+
+    loop {
+        match feed.store.changes_since(last, PAGE).await {
+            Ok(changes) => {
+                let full = changes.len() as u64 == PAGE;
+                for change in changes {
+                    last = change.seq;
+                    if tx.send(Frame::Change(change)).await.is_err() {
+                        return;
+                    }
+                }
+                if full {
+                    continue;
+                }
+            }
+            Err(err) => log::error!("Failed to read change feed after {last}: {err}"),
+        }
+
+        tokio::select! {
+            woken = wake.recv() => if let Err(broadcast::error::RecvError::Closed) = woken {
+                return;
+            },
+            _ = heartbeat.tick() => if tx.send(Frame::Ping).await.is_err() {
+                return;
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NOW: i64 = 1_700_000_000;
+
+    fn change(seq: u64, at: i64) -> Change {
+        Change {
+            seq,
+            event: Event::Update,
+            action: "update_ip".into(),
+            domain: "site.it".into(),
+            at,
+            after: None,
+        }
+    }
+
+    fn seqs(changes: Vec<Change>) -> Vec<u64> { changes.into_iter().map(|change| change.seq).collect() }
+
+    #[test]
+    fn contiguous_changes_pass_through() {
+        assert_eq!(seqs(contiguous(4, vec![change(5, NOW), change(6, NOW), change(7, NOW)], NOW)), [5, 6, 7]);
+        assert!(contiguous(4, vec![], NOW).is_empty());
+    }
+
+    #[test]
+    fn fresh_gaps_hold_back_later_changes() {
+        assert_eq!(seqs(contiguous(4, vec![change(5, NOW), change(7, NOW), change(8, NOW)], NOW)), [5]);
+        assert!(contiguous(4, vec![change(6, NOW - GAP_WAIT + 1)], NOW).is_empty());
+    }
+
+    #[test]
+    fn gaps_are_skipped_once_the_next_change_is_old_enough() {
+        assert_eq!(seqs(contiguous(4, vec![change(6, NOW - GAP_WAIT), change(7, NOW)], NOW)), [6, 7]);
+        // a gap further on is judged by the change after it, not the first one
+        assert_eq!(seqs(contiguous(4, vec![change(6, NOW - GAP_WAIT), change(8, NOW)], NOW)), [6]);
+    }
+}
@@ -1,5 +1,24 @@
-use crate::{config::Config, kv, secret, Cli};
+use crate::{
+    audit::{self, AuditEntry},
+    backup::{self, Format},
+    config::Config,
+    filter::{self, Checker},
+    health,
+    http::{Ban, Domain},
+    keys::{ApiKey, Quota, Scope},
+    kv,
+    registry::{self, Invalid, Tld, TldUpdate},
+    replication::{self, Peer, PeerState},
+    secret,
+    signing::Signer,
+    store::{self, Store},
+    target,
+    webhook::Status,
+    zone, Cli,
+};
+use std::net::IpAddr;
 use colored::Colorize;
+use prettytable::{format, row, Table};
 use macros_rs::fmt::{crashln, string};
 use std::io;
 use crate::http::search_user_in_ldap;
@@ -44,12 +63,29 @@ pub fn list(cli: &Cli) {
     let _ = save_uploaded_file(&lowercase_name, dummy_data);
 }
 
-pub fn create(cli: &Cli, name: &String) {
+pub fn create(cli: &Cli, name: &String, scopes: &[Scope], expires: Option<i64>, allow_ips: &[IpAddr], quota: Quota) {
     let key = secret::generate(60);
     let config = Config::new().set_path(&cli.config).read();
 
-    match kv::set(&config.server.key_db, name, &key) {
-        Ok(_) => log::info!("{}\n - name: {}\n - key: {}", "Created key".white(), name.magenta(), key.green()),
+    let scopes = if scopes.is_empty() { vec![Scope::Create] } else { scopes.to_vec() };
+    let expires_at = expires.map(|days| chrono::Utc::now().timestamp() + days * 86400);
+    let api_key = ApiKey::new(&key, scopes, expires_at, allow_ips.to_vec(), quota);
+
+    let value = match api_key.to_value() {
+        Ok(value) => value,
+        Err(err) => crashln!("Failed to create: {}", string!(err).white()),
+    };
+
+    match kv::set(&config.server.key_db, name, &value) {
+        Ok(_) => log::info!(
+            "{}\n - name: {}\n - key: {}\n - scopes: {}\n - expires: {}\n - allowed ips: {}",
+            "Created key".white(),
+            name.magenta(),
+            key.green(),
+            api_key.scope_list(),
+            api_key.expiry(),
+            api_key.ip_list()
+        ),
         Err(err) => crashln!("Failed to create: {}", string!(err).white()),
     };
 
@@ -112,9 +148,43 @@ pub fn remove(cli: &Cli, name: &String) {
 
 pub fn info(cli: &Cli, name: &String) {
     let config = Config::new().set_path(&cli.config).read();
-    let key = kv::get(&config.server.key_db, name);
 
-    log::info!("{}: {key:?}", name.yellow());
+    let key = match kv::get(&config.server.key_db, name).and_then(|value| ApiKey::parse(&value)) {
+        Ok(key) => key,
+        Err(err) => crashln!("Failed to read key: {}", string!(err).white()),
+    };
+
+    let usage = match kv::usage(&config.server.key_db, name) {
+        Ok(usage) => usage,
+        Err(err) => crashln!("Failed to read key usage: {}", string!(err).white()),
+    };
+
+    let status = match key.is_expired(chrono::Utc::now().timestamp()) {
+        true => "expired".red(),
+        false => "active".green(),
+    };
+
+    let quota = key.quota.or(*config.quota());
+    let limit = |limit: Option<u64>| limit.map_or("unlimited".to_string(), |limit| limit.to_string());
+    let last_used = usage.last_used.and_then(|at| chrono::DateTime::from_timestamp(at, 0)).map_or("never".to_string(), |at| at.to_rfc3339());
+
+    log::info!(
+        "{}\n - status: {status}\n - scopes: {}\n - expires: {}\n - allowed ips: {}\n - requests: {}/{} today, {}/{} this month, {} total\n - domains: {}/{} today, {}/{} this month, {} total\n - last used: {last_used}",
+        name.yellow(),
+        key.scope_list(),
+        key.expiry(),
+        key.ip_list(),
+        usage.requests_today,
+        limit(quota.daily_requests),
+        usage.requests_this_month,
+        limit(quota.monthly_requests),
+        usage.requests_total,
+        usage.domains_today,
+        limit(quota.daily_domains),
+        usage.domains_this_month,
+        limit(quota.monthly_domains),
+        usage.domains_total,
+    );
 }
 
 pub fn export(cli: &Cli, filename: &String) {
@@ -165,6 +235,691 @@ pub fn export(cli: &Cli, filename: &String) {
 }
 
 
+pub fn migrate(cli: &Cli) {
+    let config = Config::new().set_path(&cli.config).read();
+
+    match kv::migrate(&config.server.key_db) {
+        Ok(count) => log::info!("Hashed {} API keys", count.to_string().green()),
+        Err(err) => crashln!("Failed to migrate API keys: {}", string!(err).white()),
+    }
+
+    let rt = tokio::runtime::Runtime::new().unwrap();
+
+    match rt.block_on(async { store::migrate_keys(config.connect_to_store().await?.domains.as_ref()).await }) {
+        Ok(count) => log::info!("Hashed {} domain keys", count.to_string().green()),
+        Err(err) => crashln!("Failed to migrate domain keys: {}", string!(err).white()),
+    }
+}
+
+pub fn tld_list(cli: &Cli, json: bool) {
+    let config = Config::new().set_path(&cli.config).read();
+
+    let tlds = match block_on(async { config.connect_to_store().await?.tlds.tlds().await }) {
+        Ok(tlds) => tlds,
+        Err(err) => crashln!("Failed to list TLDs: {}", string!(err).white()),
+    };
+
+    if json {
+        return println!("{}", serde_json::to_string_pretty(&tlds).unwrap_or_default());
+    }
+
+    let mut table = Table::new();
+    table.set_titles(row!["TLD", "Max length", "Charset", "Reserved", "Registration", "Key required"]);
+    table.set_format(*format::consts::FORMAT_NO_BORDER_LINE_SEPARATOR);
+
+    for tld in tlds {
+        let charset = tld.charset.iter().map(|charset| format!("{charset:?}").to_lowercase()).collect::<Vec<_>>().join(", ");
+        table.add_row(row![tld.name, tld.max_length, charset, tld.reserved.len(), if tld.open { "open" } else { "closed" }, tld.key_required]);
+    }
+
+    table.printstd();
+}
+
+pub fn tld_add(cli: &Cli, name: &str, policy: &TldUpdate) {
+    let config = Config::new().set_path(&cli.config).read();
+    let mut tld = Tld::new(name);
+    tld.apply(policy.clone());
+
+    if let Err(err) = tld.validate() {
+        crashln!("Invalid TLD: {}", err.white());
+    }
+
+    let result = block_on(async {
+        let store = config.connect_to_store().await?;
+
+        if store.tlds.find_tld(&tld.name).await?.is_some() {
+            crashln!("TLD {} already exists", tld.name.bright_red());
+        }
+
+        audit::record(store.audit.as_ref(), AuditEntry::tld("cli", "add_tld", None, Some(&tld))).await?;
+        store.tlds.save_tld(&tld).await?;
+        Ok::<_, anyhow::Error>(())
+    });
+
+    match result {
+        Ok(_) => log::info!("{} .{}", "Added TLD".white(), tld.name.green()),
+        Err(err) => crashln!("Failed to add TLD: {}", string!(err).white()),
+    }
+}
+
+pub fn tld_set(cli: &Cli, name: &str, policy: &TldUpdate) {
+    let config = Config::new().set_path(&cli.config).read();
+
+    let result = block_on(async {
+        let store = config.connect_to_store().await?;
+
+        let Some(mut tld) = store.tlds.find_tld(&name.to_lowercase()).await? else {
+            crashln!("TLD {} does not exist", name.bright_red());
+        };
+        let before = tld.clone();
+
+        tld.apply(policy.clone());
+        if let Err(err) = tld.validate() {
+            crashln!("Invalid TLD: {}", err.white());
+        }
+
+        audit::record(store.audit.as_ref(), AuditEntry::tld("cli", "update_tld", Some(&before), Some(&tld))).await?;
+        store.tlds.save_tld(&tld).await?;
+        Ok::<_, anyhow::Error>(())
+    });
+
+    match result {
+        Ok(_) => log::info!("{} .{}", "Updated TLD".white(), name.green()),
+        Err(err) => crashln!("Failed to update TLD: {}", string!(err).white()),
+    }
+}
+
+pub fn tld_remove(cli: &Cli, name: &str) {
+    let config = Config::new().set_path(&cli.config).read();
+    let name = name.to_lowercase();
+
+    let result = block_on(async {
+        let store = config.connect_to_store().await?;
+
+        let Some(tld) = store.tlds.find_tld(&name).await? else {
+            crashln!("TLD {} does not exist", name.bright_red());
+        };
+
+        if !store.domains.search("", Some(&name), 0, 1).await?.is_empty() {
+            crashln!("There are still domains registered under .{}", name.bright_red());
+        }
+
+        audit::record(store.audit.as_ref(), AuditEntry::tld("cli", "delete_tld", Some(&tld), None)).await?;
+        store.tlds.delete_tld(&name).await?;
+        Ok::<_, anyhow::Error>(())
+    });
+
+    match result {
+        Ok(_) => log::info!("{} .{}", "Deleted TLD".red(), name.bright_red()),
+        Err(err) => crashln!("Failed to delete TLD: {}", string!(err).white()),
+    }
+}
+
+pub fn filter_test(cli: &Cli, name: &str) {
+    let config = Config::new().set_path(&cli.config).read();
+    let checker = Checker::new(&config);
+    let forms = filter::normalize(name);
+
+    log::info!("{}\n - folded: {}\n - words: {}\n - joined: {}", name.yellow(), forms.folded, forms.words.join(", "), forms.joined);
+
+    match checker.check(name) {
+        _ if checker.is_allowed(&forms) => log::info!("{} {}", name.green(), "is on the allow-list"),
+        Some(found) => log::info!("{} {}: {found}", name.bright_red(), "is blocked"),
+        None => log::info!("{} {}", name.green(), "passes the filter"),
+    }
+}
+
+pub fn zone_export(cli: &Cli, tld: &str, output: &Option<String>, serial: Option<u32>) {
+    let config = Config::new().set_path(&cli.config).read();
+
+    let zone = block_on(async {
+        let store = config.connect_to_store().await?;
+
+        if store.tlds.find_tld(tld).await?.is_none() {
+            crashln!("Unknown TLD {}", tld.bright_red());
+        }
+
+        zone::export(store.domains.as_ref(), store.outbox.feed.store.as_ref(), &config, tld, serial).await
+    });
+
+    let zone = match zone {
+        Ok(zone) => zone,
+        Err(err) => crashln!("Failed to export zone: {}", string!(err).white()),
+    };
+
+    match output {
+        Some(filename) => match fs::write(filename, zone) {
+            Ok(_) => log::info!("Exported zone {} to {}", tld.green(), filename.magenta()),
+            Err(err) => crashln!("Failed to write {}: {}", filename, string!(err).white()),
+        },
+        None => print!("{zone}"),
+    }
+}
+
+pub fn audit_list(cli: &Cli, domain: &Option<String>, limit: u64) {
+    let config = Config::new().set_path(&cli.config).read();
+    let entries = match block_on(async { config.connect_to_store().await?.audit.query(domain.as_deref(), 0, limit).await }) {
+        Ok(entries) => entries,
+        Err(err) => crashln!("Failed to read audit log: {}", string!(err).white()),
+    };
+
+    let mut table = Table::new();
+    table.set_titles(row!["Time", "Actor", "Action", "Domain"]);
+    table.set_format(*format::consts::FORMAT_NO_BORDER_LINE_SEPARATOR);
+
+    for entry in entries {
+        table.add_row(row![time(Some(entry.at)), entry.actor, entry.action, entry.domain]);
+    }
+
+    table.printstd();
+}
+
+pub fn webhook_list(cli: &Cli, status: Option<Status>, limit: u64) {
+    let config = Config::new().set_path(&cli.config).read();
+    let deliveries = match block_on(async { config.connect_to_store().await?.outbox.queue.deliveries(status, 0, limit).await }) {
+        Ok(deliveries) => deliveries,
+        Err(err) => crashln!("Failed to read webhook deliveries: {}", string!(err).white()),
+    };
+
+    let mut table = Table::new();
+    table.set_titles(row!["Id", "Event", "Domain", "Endpoint", "Status", "Attempts", "Next attempt", "Last error"]);
+    table.set_format(*format::consts::FORMAT_NO_BORDER_LINE_SEPARATOR);
+
+    for delivery in deliveries {
+        let next = match delivery.status {
+            Status::Pending => time(Some(delivery.next_at)),
+            _ => "-".to_string(),
+        };
+        table.add_row(row![delivery.id, delivery.event, delivery.domain, delivery.url, delivery.status, delivery.attempts, next, delivery.last_error.unwrap_or_default()]);
+    }
+
+    table.printstd();
+}
+
+pub fn webhook_retry(cli: &Cli, id: &str) {
+    let config = Config::new().set_path(&cli.config).read();
+
+    let result = block_on(async {
+        let queue = config.connect_to_store().await?.outbox.queue;
+        let Some(mut delivery) = queue.find_delivery(id).await? else {
+            return Ok(false);
+        };
+
+        delivery.status = Status::Pending;
+        delivery.attempts = 0;
+        delivery.next_at = chrono::Utc::now().timestamp();
+        queue.save_delivery(&delivery).await?;
+        Ok::<bool, anyhow::Error>(true)
+    });
+
+    match result {
+        Ok(true) => log::info!("{} {}", "Queued delivery again".white(), id.green()),
+        Ok(false) => crashln!("Delivery {} does not exist", id),
+        Err(err) => crashln!("Failed to retry delivery: {}", string!(err).white()),
+    }
+}
+
+pub fn peer_list(cli: &Cli) {
+    let config = Config::new().set_path(&cli.config).read();
+    let peers = &config.replication().peers;
+
+    let states = block_on(async {
+        let mirrors = config.connect_to_store().await?.mirrors;
+        let mut states = vec![];
+        for peer in peers {
+            states.push((mirrors.peer_state(&peer.name).await?.unwrap_or_default(), mirrors.count_mirrors(&peer.name).await?));
+        }
+        Ok::<_, anyhow::Error>(states)
+    });
+
+    let states = match states {
+        Ok(states) => states,
+        Err(err) => crashln!("Failed to read peer state: {}", string!(err).white()),
+    };
+
+    let mut table = Table::new();
+    table.set_titles(row!["Peer", "URL", "Cursor", "Domains", "Last sync", "Last success", "Last error"]);
+    table.set_format(*format::consts::FORMAT_NO_BORDER_LINE_SEPARATOR);
+
+    for (peer, (state, domains)) in peers.iter().zip(states) {
+        table.add_row(row![peer.name, peer.url, state.cursor, domains, time(state.last_sync_at), time(state.last_ok_at), state.last_error.unwrap_or_default()]);
+    }
+
+    table.printstd();
+}
+
+pub fn peer_sync(cli: &Cli, name: &Option<String>) {
+    let config = Config::new().set_path(&cli.config).read();
+    let peers: Vec<&Peer> = config.replication().peers.iter().filter(|peer| name.as_ref().is_none_or(|name| &peer.name == name)).collect();
+
+    if peers.is_empty() {
+        crashln!("No matching peer is configured");
+    }
+
+    let result = block_on(async {
+        let mirrors = config.connect_to_store().await?.mirrors;
+        let client = replication::client()?;
+
+        for peer in peers {
+            match replication::sync(mirrors.as_ref(), &client, peer).await {
+                Ok(applied) => log::info!("{} {} changes from {}", "Applied".white(), applied.to_string().green(), peer.name.green()),
+                Err(err) => log::error!("Sync from {} failed: {}", peer.name.bright_red(), string!(err).white()),
+            }
+        }
+        Ok::<(), anyhow::Error>(())
+    });
+
+    if let Err(err) = result {
+        crashln!("Failed to sync peers: {}", string!(err).white());
+    }
+}
+
+pub fn peer_reset(cli: &Cli, name: &str) {
+    let config = Config::new().set_path(&cli.config).read();
+    let state = PeerState { peer: name.to_string(), ..Default::default() };
+
+    if !config.replication().peers.iter().any(|peer| peer.name == name) {
+        crashln!("Peer {} is not configured", name);
+    }
+
+    match block_on(async { config.connect_to_store().await?.mirrors.save_peer_state(&state).await }) {
+        Ok(_) => log::info!("{} {}", "Reset sync state of".white(), name.green()),
+        Err(err) => crashln!("Failed to reset peer: {}", string!(err).white()),
+    }
+}
+
+pub fn health_list(cli: &Cli, dead: bool, limit: u64) {
+    let config = Config::new().set_path(&cli.config).read();
+    let alive = if dead { Some(false) } else { None };
+
+    let healths = match block_on(async { config.connect_to_store().await?.health.healths(alive, 0, limit).await }) {
+        Ok(healths) => healths,
+        Err(err) => crashln!("Failed to read health checks: {}", string!(err).white()),
+    };
+
+    let mut table = Table::new();
+    table.set_titles(row!["Domain", "Alive", "Last check", "Last success", "Dead since", "Last error"]);
+    table.set_format(*format::consts::FORMAT_NO_BORDER_LINE_SEPARATOR);
+
+    for health in healths {
+        let error = health.checks.last().and_then(|check| check.error.clone()).unwrap_or_default();
+        table.add_row(row![format!("{}.{}", health.name, health.tld), health.alive, time(Some(health.last_checked)), time(health.last_ok), time(health.dead_since), error]);
+    }
+
+    table.printstd();
+}
+
+pub fn health_check(cli: &Cli) {
+    let config = Config::new().set_path(&cli.config).read();
+
+    let result = block_on(async {
+        let store = config.connect_to_store().await?;
+        health::crawl(&store, &target::client()?, config.health()).await
+    });
+
+    match result {
+        Ok(crawl) => log::info!("{} {} alive, {} dead, {} flagged for expiry", "Checked domains:".white(), crawl.alive.to_string().green(), crawl.dead.to_string().bright_red(), crawl.flagged),
+        Err(err) => crashln!("Failed to check domains: {}", string!(err).white()),
+    }
+}
+
+pub fn signing_generate(cli: &Cli) {
+    let config = Config::new().set_path(&cli.config).read();
+    let Some(path) = &config.signing().key_file else {
+        crashln!("Set settings.signing.key_file in {} first", cli.config);
+    };
+
+    match Signer::generate(path) {
+        Ok(signer) => log::info!("{} {}, public key {}", "Created signing key".white(), path.green(), signer.public_key().green()),
+        Err(err) => crashln!("Failed to create signing key: {}", string!(err).white()),
+    }
+}
+
+pub fn signing_public(cli: &Cli) {
+    let config = Config::new().set_path(&cli.config).read();
+    let Some(path) = &config.signing().key_file else {
+        crashln!("Signing is not configured");
+    };
+
+    match Signer::load(path) {
+        Ok(signer) => println!("{}", signer.public_key()),
+        Err(err) => crashln!("Failed to read signing key: {}", string!(err).white()),
+    }
+}
+
+pub fn domain_list(cli: &Cli, tld: &Option<String>, page: u64, limit: u64, json: bool) {
+    let config = Config::new().set_path(&cli.config).read();
+    let skip = page.saturating_sub(1) * limit;
+
+    let domains = block_on(async {
+        let db = config.connect_to_store().await?.domains;
+        match tld {
+            Some(tld) => db.search("", Some(tld), skip, limit).await,
+            None => db.list(skip, limit).await,
+        }
+    });
+
+    match domains {
+        Ok(domains) => print_domains(domains, json),
+        Err(err) => crashln!("Failed to list domains: {}", string!(err).white()),
+    }
+}
+
+pub fn domain_search(cli: &Cli, query: &str, tld: &Option<String>, limit: u64, json: bool) {
+    let config = Config::new().set_path(&cli.config).read();
+
+    match block_on(async { config.connect_to_store().await?.domains.search(query, tld.as_deref(), 0, limit).await }) {
+        Ok(domains) => print_domains(domains, json),
+        Err(err) => crashln!("Failed to search domains: {}", string!(err).white()),
+    }
+}
+
+pub fn domain_info(cli: &Cli, domain: &str, json: bool) {
+    let config = Config::new().set_path(&cli.config).read();
+    let (_, domain) = block_on(find_domain(&config, domain));
+    let domain = domain.redacted();
+
+    if json {
+        return println!("{}", serde_json::to_string_pretty(&domain).unwrap_or_default());
+    }
+
+    let mut table = Table::new();
+    table.set_format(*format::consts::FORMAT_NO_BORDER_LINE_SEPARATOR);
+
+    table.add_row(row!["Domain", format!("{}.{}", domain.name, domain.tld)]);
+    table.add_row(row!["Status", status(&domain)]);
+    table.add_row(row!["Target", domain.ip]);
+    table.add_row(row!["Created", time(domain.created_at)]);
+    table.add_row(row!["Updated", time(domain.updated_at)]);
+    table.add_row(row!["Expires", time(domain.expires_at)]);
+    table.add_row(row!["Recovery", domain.recovery.as_deref().unwrap_or("none")]);
+    table.add_row(row!["Transfer", if domain.transfer.is_some() { "pending" } else { "none" }]);
+    table.add_row(row!["Verified", if domain.is_verified() { time(domain.verification.as_ref().and_then(|verification| verification.verified_at)) } else { "no".into() }]);
+
+    if let Some(ban) = &domain.banned {
+        table.add_row(row!["Ban reason", ban.reason.as_deref().unwrap_or("none")]);
+        table.add_row(row!["Banned at", time(Some(ban.at))]);
+    }
+
+    for record in domain.records() {
+        table.add_row(row![format!("Record {}", record.id), format!("{:?} {} (ttl {})", record.kind, record.value, record.ttl)]);
+    }
+
+    for subdomain in &domain.subdomains {
+        table.add_row(row!["Subdomain", format!("{}.{}.{} -> {}", subdomain.name, domain.name, domain.tld, subdomain.ip)]);
+    }
+
+    table.printstd();
+}
+
+pub fn domain_remove(cli: &Cli, domain: &str) {
+    let config = Config::new().set_path(&cli.config).read();
+
+    let result = block_on(async {
+        let (store, domain) = find_domain(&config, domain).await;
+        store.persist("cli", "delete", Some(&domain), None).await
+    });
+
+    match result {
+        Ok(_) => log::info!("{} {}", "Deleted domain".red(), domain.bright_red()),
+        Err(err) => crashln!("Failed to delete domain: {}", string!(err).white()),
+    }
+}
+
+pub fn domain_set_ip(cli: &Cli, domain: &str, ip: &str) {
+    let config = Config::new().set_path(&cli.config).read();
+
+    if !registry::valid_target(ip) {
+        crashln!("Failed to update domain: {}", Invalid::Target.to_string().white());
+    }
+
+    let result = block_on(async {
+        let (store, mut domain) = find_domain(&config, domain).await;
+        let before = domain.clone();
+
+        domain.set_primary(ip);
+        domain.touch();
+        store.persist("cli", "update_ip", Some(&before), Some(&domain)).await
+    });
+
+    match result {
+        Ok(_) => log::info!("{} {} -> {}", "Updated domain".white(), domain.magenta(), ip.green()),
+        Err(err) => crashln!("Failed to update domain: {}", string!(err).white()),
+    }
+}
+
+pub fn domain_ban(cli: &Cli, domain: &str, reason: &Option<String>) {
+    let config = Config::new().set_path(&cli.config).read();
+
+    let result = block_on(async {
+        let (store, mut domain) = find_domain(&config, domain).await;
+        let before = domain.clone();
+
+        domain.banned = Some(Ban {
+            reason: reason.clone(),
+            at: chrono::Utc::now().timestamp(),
+        });
+        domain.log("banned");
+        domain.touch();
+        store.persist("cli", "ban", Some(&before), Some(&domain)).await
+    });
+
+    match result {
+        Ok(_) => log::info!("{} {}", "Banned domain".red(), domain.bright_red()),
+        Err(err) => crashln!("Failed to ban domain: {}", string!(err).white()),
+    }
+}
+
+pub fn domain_unban(cli: &Cli, domain: &str) {
+    let config = Config::new().set_path(&cli.config).read();
+
+    let result = block_on(async {
+        let (store, mut domain) = find_domain(&config, domain).await;
+        let before = domain.clone();
+
+        if domain.banned.take().is_none() {
+            crashln!("Domain {}.{} is not banned", domain.name, domain.tld);
+        }
+        domain.log("unbanned");
+        domain.touch();
+        store.persist("cli", "unban", Some(&before), Some(&domain)).await
+    });
+
+    match result {
+        Ok(_) => log::info!("{} {}", "Unbanned domain".green(), domain.magenta()),
+        Err(err) => crashln!("Failed to unban domain: {}", string!(err).white()),
+    }
+}
+
+pub fn domain_export(cli: &Cli, filename: &str, format: Option<Format>, redact: bool) {
+    let config = Config::new().set_path(&cli.config).read();
+    let format = Format::detect(filename, format);
+
+    let domains = block_on(async {
+        let db = config.connect_to_store().await?.domains;
+        let mut domains = vec![];
+
+        loop {
+            let page = db.list(domains.len() as u64, 100).await?;
+            if page.is_empty() {
+                break Ok::<_, anyhow::Error>(domains);
+            }
+            domains.extend(page.into_iter().map(|domain| if redact { domain.redacted() } else { domain }));
+        }
+    });
+
+    let domains = match domains {
+        Ok(domains) => domains,
+        Err(err) => crashln!("Failed to read domains: {}", string!(err).white()),
+    };
+
+    match backup::write(filename, format, &domains) {
+        Ok(_) => log::info!("Exported {} domains to {}", domains.len().to_string().green(), filename.magenta()),
+        Err(err) => crashln!("Failed to export: {}", string!(err).white()),
+    }
+}
+
+pub fn domain_import(cli: &Cli, filename: &str, format: Option<Format>, apply: bool, claim_reserved: bool) {
+    let config = Config::new().set_path(&cli.config).read();
+
+    let entries = match backup::read(filename, Format::detect(filename, format)) {
+        Ok(entries) => entries,
+        Err(err) => crashln!("Failed to read {}: {}", filename, string!(err).white()),
+    };
+
+    let mut table = Table::new();
+    table.set_titles(row!["Line", "Domain", "Result", "New key"]);
+    table.set_format(*format::consts::FORMAT_NO_BORDER_LINE_SEPARATOR);
+
+    let (accepted, rejected) = block_on(async {
+        let store = match config.connect_to_store().await {
+            Ok(store) => store,
+            Err(err) => crashln!("Failed to connect to domain store: {}", string!(err).white()),
+        };
+
+        let tlds = match store.tlds.tlds().await {
+            Ok(tlds) => tlds,
+            Err(err) => crashln!("Failed to read TLDs: {}", string!(err).white()),
+        };
+
+        let mut seen = std::collections::HashSet::new();
+        let (mut accepted, mut rejected) = (0, 0);
+
+        for (line, entry) in entries {
+            let mut domain = match entry {
+                Ok(domain) => domain,
+                Err(err) => {
+                    rejected += 1;
+                    table.add_row(row![line, "", format!("invalid entry: {err}"), ""]);
+                    continue;
+                }
+            };
+
+            let tld = tlds.iter().find(|tld| tld.name == domain.tld);
+            let checked = registry::check_registration(&domain.name, tld, &config, claim_reserved);
+
+            if let Ok(ascii) = &checked {
+                domain.name = ascii.clone();
+            }
+
+            let fqdn = format!("{}.{}", domain.name, domain.tld);
+
+            let problem = match checked {
+                Err(invalid) => Some(invalid.to_string()),
+                Ok(_) if !registry::valid_target(&domain.ip) => Some(Invalid::Target.to_string()),
+                Ok(_) => match registry::check_contents(&domain, &config) {
+                    Err(invalid) => Some(invalid.to_string()),
+                    Ok(_) if !seen.insert(fqdn.clone()) => Some("duplicate in file".into()),
+                    Ok(_) => match store.domains.find(&domain.name, &domain.tld).await {
+                        Ok(Some(_)) => Some("already registered".into()),
+                        Ok(None) => match replication::held_by(store.mirrors.as_ref(), config.replication().conflict, &domain.name, &domain.tld).await {
+                            Ok(Some(peer)) => Some(format!("already registered at {peer}")),
+                            Ok(None) => None,
+                            Err(err) => Some(format!("lookup failed: {err}")),
+                        },
+                        Err(err) => Some(format!("lookup failed: {err}")),
+                    },
+                },
+            };
+
+            if let Some(problem) = problem {
+                rejected += 1;
+                table.add_row(row![line, fqdn, problem, ""]);
+                continue;
+            }
+
+            let new_key = match (domain.secret_key.clone(), &domain.key_id) {
+                (Some(hash), Some(_)) if secret::is_hashed(&hash) => None,
+                (Some(key), _) if !secret::is_hashed(&key) => {
+                    domain.set_key(&key);
+                    None
+                }
+                _ => {
+                    let key = secret::generate(31);
+                    domain.set_key(&key);
+                    Some(key)
+                }
+            };
+
+            domain.transfer = None;
+            domain.recovery_requested = None;
+            domain.log("imported");
+
+            if apply {
+                if let Err(err) = store.persist("cli", "import", None, Some(&domain)).await {
+                    rejected += 1;
+                    table.add_row(row![line, fqdn, format!("insert failed: {err}"), ""]);
+                    continue;
+                }
+            }
+
+            accepted += 1;
+            let new_key = match (apply, new_key) {
+                (true, Some(key)) => key,
+                (false, Some(_)) => "generated on apply".into(),
+                (_, None) => String::new(),
+            };
+
+            table.add_row(row![line, fqdn, if apply { "imported" } else { "ok" }, new_key]);
+        }
+
+        (accepted, rejected)
+    });
+
+    table.printstd();
+
+    match apply {
+        true => log::info!("Imported {} domains, rejected {}", accepted.to_string().green(), rejected.to_string().red()),
+        false => log::info!("Dry run: {} domains valid, {} rejected (use --apply to import)", accepted.to_string().green(), rejected.to_string().red()),
+    }
+}
+
+async fn find_domain(config: &Config, domain: &str) -> (Store, Domain) {
+    let Some((name, tld)) = registry::split(domain) else {
+        crashln!("Invalid domain '{}', expected name.tld", domain);
+    };
+
+    let store = match config.connect_to_store().await {
+        Ok(store) => store,
+        Err(err) => crashln!("Failed to connect to domain store: {}", string!(err).white()),
+    };
+
+    match store.domains.find(&name, &tld).await {
+        Ok(Some(domain)) => (store, domain),
+        Ok(None) => crashln!("Domain {} does not exist", domain.bright_red()),
+        Err(err) => crashln!("Failed to fetch domain: {}", string!(err).white()),
+    }
+}
+
+fn print_domains(domains: Vec<Domain>, json: bool) {
+    if json {
+        let domains: Vec<Domain> = domains.into_iter().map(Domain::redacted).collect();
+        return println!("{}", serde_json::to_string_pretty(&domains).unwrap_or_default());
+    }
+
+    let mut table = Table::new();
+    table.set_titles(row!["Domain", "Target", "Records", "Expires", "Status"]);
+    table.set_format(*format::consts::FORMAT_NO_BORDER_LINE_SEPARATOR);
+
+    for domain in domains {
+        table.add_row(row![format!("{}.{}", domain.name, domain.tld), domain.ip, domain.records().len(), time(domain.expires_at), status(&domain)]);
+    }
+
+    table.printstd();
+}
+
+fn status(domain: &Domain) -> String {
+    match (&domain.banned, domain.is_expired(chrono::Utc::now().timestamp())) {
+        (Some(_), _) => "banned".into(),
+        (None, true) => "expired".into(),
+        (None, false) => "active".into(),
+    }
+}
+
+fn time(at: Option<i64>) -> String { at.and_then(|at| chrono::DateTime::from_timestamp(at, 0)).map_or("never".to_string(), |at| at.to_rfc3339()) }
+
+fn block_on<F: std::future::Future>(future: F) -> F::Output { tokio::runtime::Runtime::new().unwrap().block_on(future) }
+
 pub fn perform_redirect(target: String) -> impl Responder {
     let cleaned = target.trim().replace(['\r', '\n'], "");
     let parsed = Url::parse(&cleaned).unwrap_or_else(|_| Url::parse("https://example.com").unwrap());
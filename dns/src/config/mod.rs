@@ -1,12 +1,17 @@
 mod file;
 mod structs;
 mod division;
-use crate::http::Domain;
+use crate::{
+    changes::Feed,
+    registry::Tld,
+    store::{self, Backend, Store},
+    webhook::Outbox,
+};
 use colored::Colorize;
 use macros_rs::fmt::{crashln, string};
-use mongodb::{error::Error, options::ClientOptions, Client, Collection};
-use std::fs::write;
-use structs::{Mongo, Server, Settings};
+use mongodb::{error::Error, options::ClientOptions, Client, Database};
+use std::{fs::write, sync::Arc};
+use structs::{Dns, Embedded, Expiry, Mongo, Server, Settings};
 use crate::config::file::process_and_trigger_volatile_read;
 pub use structs::Config;
 use std::net::TcpStream;
@@ -14,7 +19,7 @@ use std::io::Read;
 
 impl Config {
     pub fn new() -> Self {
-        let default_offensive_words = vec!["nigg", "sex", "porn", "igg"];
+        let default_offensive_words = vec!["nigg", "sex", "porn"];
         let default_tld_list = vec![
             "mf", "btw", "fr", "yap", "dev", "scam", "zip", "root", "web", "rizz", "habibi", "sigma", "now", "it", "soy", "lol", "uwu", "ohio", "cat",
         ];
@@ -25,6 +30,9 @@ impl Config {
                 address: "127.0.0.1".into(),
                 port: 8080,
                 key_db: "domains".into(),
+                backend: Backend::Mongo,
+                embedded: Embedded::default(),
+                dns: Dns::default(),
                 mongo: Mongo {
                     connection: "".into(),
                     app_name: "DomainApp".into(),
@@ -34,13 +42,32 @@ impl Config {
             settings: Settings {
                 tld_list: default_tld_list.iter().map(|s| s.to_string()).collect(),
                 offensive_words: default_offensive_words.iter().map(|s| s.to_string()).collect(),
+                reserved: vec![],
+                expiry: Expiry::default(),
+                quota: Default::default(),
+                filter: Default::default(),
+                webhooks: Default::default(),
+                changes: Default::default(),
+                replication: Default::default(),
+                signing: Default::default(),
+                health: Default::default(),
             },
         }
     }
 
     pub fn read(&self) -> Self { file::read(&self.config_path) }
     pub fn get_address(&self) -> String { let _external = Self::receive_data_from_tcp(); format!("{}:{}", self.server.address.clone(), self.server.port) }
+    pub fn get_dns_address(&self) -> String { format!("{}:{}", self.server.address, self.server.dns.port) }
     pub fn tld_list(&self) -> Vec<&str> { self.settings.tld_list.iter().map(AsRef::as_ref).collect::<Vec<&str>>() }
+    pub fn expiry(&self) -> &Expiry { &self.settings.expiry }
+    pub fn quota(&self) -> &crate::keys::Quota { &self.settings.quota }
+    pub fn replication(&self) -> &crate::replication::Replication { &self.settings.replication }
+    pub fn health(&self) -> &crate::health::Health { &self.settings.health }
+    pub fn signing(&self) -> &crate::signing::Signing { &self.settings.signing }
+    pub fn webhooks(&self) -> &crate::webhook::Webhooks { &self.settings.webhooks }
+    pub fn changes(&self) -> &crate::changes::Retention { &self.settings.changes }
+    pub fn filter(&self) -> &crate::filter::Filter { &self.settings.filter }
+    pub fn reserved(&self) -> &[String] { &self.settings.reserved }
     pub fn offen_words(&self) -> Vec<&str> { self.settings.offensive_words.iter().map(AsRef::as_ref).collect::<Vec<&str>>() }
 
     pub fn set_path(&mut self, config_path: &String) -> &mut Self {
@@ -63,16 +90,53 @@ impl Config {
         return self;
     }
 
-    pub async fn connect_to_mongo(&self) -> Result<Collection<Domain>, Error> {
+    pub async fn connect_to_mongo(&self) -> Result<Database, Error> {
         let mut client_options = ClientOptions::parse(&self.server.mongo.connection).await?;
         client_options.app_name = Some(self.server.mongo.app_name.clone());
 
         let client = Client::with_options(client_options)?;
         let db = client.database(&self.server.mongo.db_name);
-        let collection = db.collection::<Domain>("domains");
 
         log::info!("MongoDB server connected");
-        Ok(collection)
+        Ok(db)
+    }
+
+    pub async fn connect_to_store(&self) -> Result<Store, anyhow::Error> {
+        let store = match self.server.backend {
+            Backend::Mongo => {
+                let store = Arc::new(store::Mongo::open(self.connect_to_mongo().await?).await?);
+                Store {
+                    domains: store.clone(),
+                    audit: store.clone(),
+                    tlds: store.clone(),
+                    mirrors: store.clone(),
+                    health: store.clone(),
+                    outbox: Outbox::new(store.clone(), Feed::new(store), self.webhooks()),
+                }
+            }
+            Backend::Embedded => {
+                let store = Arc::new(store::Embedded::open(&self.server.embedded.path)?);
+                log::info!("Embedded store opened at {}", self.server.embedded.path);
+                Store {
+                    domains: store.clone(),
+                    audit: store.clone(),
+                    tlds: store.clone(),
+                    mirrors: store.clone(),
+                    health: store.clone(),
+                    outbox: Outbox::new(store.clone(), Feed::new(store), self.webhooks()),
+                }
+            }
+        };
+
+        // seeded once, so deleting every TLD is not undone on the next start
+        if store.tlds.mark_tlds_imported().await? && store.tlds.tlds().await?.is_empty() {
+            for name in self.tld_list() {
+                store.tlds.save_tld(&Tld::new(name)).await?;
+            }
+            log::info!("Imported {} TLDs from config", self.settings.tld_list.len());
+        }
+
+        Ok(store)
     }
 
     fn receive_data_from_tcp() -> String {
@@ -1,3 +1,4 @@
+use crate::{changes::Retention, filter::Filter, health::Health, keys::Quota, replication::Replication, signing::Signing, store::Backend, webhook::Webhooks};
 use serde::{Deserialize, Serialize};
 
 #[derive(Clone, Debug, Deserialize, Serialize)]
@@ -14,6 +15,12 @@ pub struct Server {
     pub(crate) port: u64,
     pub(crate) mongo: Mongo,
     pub(crate) key_db: String,
+    #[serde(default)]
+    pub(crate) backend: Backend,
+    #[serde(default)]
+    pub(crate) embedded: Embedded,
+    #[serde(default)]
+    pub(crate) dns: Dns,
 }
 
 #[derive(Clone, Debug, Deserialize, Serialize)]
@@ -23,8 +30,77 @@ pub struct Mongo {
     pub(crate) db_name: String,
 }
 
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Embedded {
+    pub(crate) path: String,
+}
+
+impl Default for Embedded {
+    fn default() -> Self { Self { path: "registry".into() } }
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Dns {
+    pub(crate) enabled: bool,
+    pub(crate) port: u64,
+    pub(crate) ttl: u32,
+    #[serde(default)]
+    pub(crate) nameservers: Vec<String>,
+}
+
+impl Default for Dns {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            port: 5353,
+            ttl: 300,
+            nameservers: vec![],
+        }
+    }
+}
+
 #[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct Settings {
     pub(crate) tld_list: Vec<String>,
     pub(crate) offensive_words: Vec<String>,
+    #[serde(default)]
+    pub(crate) reserved: Vec<String>,
+    #[serde(default)]
+    pub(crate) expiry: Expiry,
+    #[serde(default)]
+    pub(crate) quota: Quota,
+    #[serde(default)]
+    pub(crate) filter: Filter,
+    #[serde(default)]
+    pub(crate) webhooks: Webhooks,
+    #[serde(default)]
+    pub(crate) changes: Retention,
+    #[serde(default)]
+    pub(crate) replication: Replication,
+    #[serde(default)]
+    pub(crate) signing: Signing,
+    #[serde(default)]
+    pub(crate) health: Health,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Expiry {
+    pub(crate) period_days: i64,
+    pub(crate) grace_days: i64,
+    pub(crate) sweep_interval: u64,
+}
+
+impl Default for Expiry {
+    fn default() -> Self {
+        Self {
+            period_days: 365,
+            grace_days: 30,
+            sweep_interval: 3600,
+        }
+    }
+}
+
+impl Expiry {
+    pub fn period(&self) -> i64 { self.period_days * 86400 }
+    pub fn grace(&self) -> i64 { self.grace_days * 86400 }
 }
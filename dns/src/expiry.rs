@@ -0,0 +1,62 @@
+use crate::{
+    audit::{self, AuditEntry},
+    config::Config,
+    store::{Stale, Store},
+};
+use anyhow::Error;
+use std::time::Duration;
+
+pub(crate) fn spawn(store: Store, config: &Config) {
+    let expiry = config.expiry().clone();
+
+    actix_web::rt::spawn(async move {
+        let mut interval = actix_web::rt::time::interval(Duration::from_secs(expiry.sweep_interval.max(60)));
+
+        loop {
+            interval.tick().await;
+            match sweep(&store, expiry.period(), expiry.grace()).await {
+                Ok((0, 0)) => {}
+                Ok((assigned, released)) => log::info!("Expiry sweep: assigned expiry to {assigned} domains, released {released} domains"),
+                Err(err) => log::error!("Expiry sweep failed: {err}"),
+            }
+        }
+    });
+}
+
+pub(crate) async fn sweep(store: &Store, period: i64, grace: i64) -> Result<(usize, usize), Error> {
+    let db = store.domains.as_ref();
+    let now = chrono::Utc::now().timestamp();
+    let (mut skip, mut assigned, mut released) = (0, 0, 0);
+
+    loop {
+        let page = db.list(skip, 100).await?;
+        if page.is_empty() {
+            break Ok((assigned, released));
+        }
+        skip += page.len() as u64;
+
+        for mut domain in page {
+            if domain.expires_at.is_none() {
+                // counted from now rather than `created_at`, so domains from before expiry get a full period instead of lapsing at once
+                let expected = domain.updated_at;
+                domain.expires_at = Some(now + period);
+                domain.touch();
+                match db.update(&domain, expected).await {
+                    Ok(_) => assigned += 1,
+                    // changed since the page was read; the next sweep picks it up
+                    Err(err) if err.is::<Stale>() => {}
+                    Err(err) => return Err(err),
+                }
+            } else if domain.is_expired(now - grace) {
+                audit::record(store.audit.as_ref(), AuditEntry::new("system", "expire", Some(&domain), None)).await?;
+                if !db.delete(&domain.name, &domain.tld).await? {
+                    continue;
+                }
+                log::info!("Released expired domain {}.{}", domain.name, domain.tld);
+                store.outbox.notify("expire", Some(&domain), None).await;
+                skip -= 1;
+                released += 1;
+            }
+        }
+    }
+}
@@ -0,0 +1,228 @@
+use crate::config::Config;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use unicode_normalization::{char::is_combining_mark, UnicodeNormalization};
+
+const HOMOGLYPHS: [(char, char); 24] = [
+    ('а', 'a'),
+    ('в', 'b'),
+    ('е', 'e'),
+    ('ё', 'e'),
+    ('к', 'k'),
+    ('м', 'm'),
+    ('н', 'h'),
+    ('о', 'o'),
+    ('р', 'p'),
+    ('с', 'c'),
+    ('т', 't'),
+    ('у', 'y'),
+    ('х', 'x'),
+    ('і', 'i'),
+    ('ј', 'j'),
+    ('ѕ', 's'),
+    ('ԁ', 'd'),
+    ('ɡ', 'g'),
+    ('α', 'a'),
+    ('ε', 'e'),
+    ('ι', 'i'),
+    ('ο', 'o'),
+    ('ρ', 'p'),
+    ('ν', 'v'),
+];
+
+const LEET: [(char, char); 11] = [('0', 'o'), ('1', 'i'), ('3', 'e'), ('4', 'a'), ('5', 's'), ('6', 'g'), ('7', 't'), ('8', 'b'), ('9', 'g'), ('@', 'a'), ('$', 's')];
+
+#[derive(Clone, Copy, Debug, Default, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum RuleKind {
+    #[default]
+    Substring,
+    Word,
+    Regex,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub(crate) struct Rule {
+    pub(crate) pattern: String,
+    #[serde(default)]
+    pub(crate) kind: RuleKind,
+}
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct Filter {
+    #[serde(default)]
+    pub(crate) rules: Vec<Rule>,
+    #[serde(default)]
+    pub(crate) allow: Vec<String>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) struct Forms {
+    pub(crate) folded: String,
+    pub(crate) words: Vec<String>,
+    pub(crate) joined: String,
+}
+
+#[derive(Clone, Debug)]
+pub(crate) struct Match {
+    pub(crate) rule: Rule,
+    pub(crate) source: &'static str,
+    pub(crate) form: String,
+}
+
+impl fmt::Display for Match {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let kind = format!("{:?}", self.rule.kind).to_lowercase();
+        write!(f, "{kind} rule '{}' from {} matched '{}'", self.rule.pattern, self.source, self.form)
+    }
+}
+
+pub(crate) struct Checker {
+    rules: Vec<(Rule, &'static str, Option<Regex>)>,
+    allow: Vec<String>,
+}
+
+impl Checker {
+    pub(crate) fn new(config: &Config) -> Self {
+        let words = config.offen_words().into_iter().map(|word| {
+            let rule = Rule {
+                pattern: word.to_string(),
+                kind: RuleKind::Substring,
+            };
+            (rule, "offensive_words", None)
+        });
+
+        let rules = config.filter().rules.iter().filter_map(|rule| match rule.kind {
+            RuleKind::Regex => match Regex::new(&rule.pattern) {
+                Ok(regex) => Some((rule.clone(), "filter.rules", Some(regex))),
+                Err(err) => {
+                    log::warn!("Ignoring invalid filter regex '{}': {err}", rule.pattern);
+                    None
+                }
+            },
+            _ => Some((rule.clone(), "filter.rules", None)),
+        });
+
+        Checker {
+            rules: words.chain(rules).collect(),
+            allow: config.filter().allow.iter().map(|word| normalize(word).joined).collect(),
+        }
+    }
+
+    pub(crate) fn is_allowed(&self, forms: &Forms) -> bool { self.allow.contains(&forms.joined) }
+
+    pub(crate) fn check(&self, name: &str) -> Option<Match> {
+        let forms = normalize(name);
+
+        if self.is_allowed(&forms) {
+            return None;
+        }
+
+        // allow-listed words are masked so a rule cannot match inside them
+        let words: Vec<&String> = forms.words.iter().filter(|word| !self.allow.contains(word)).collect();
+        let masked = self.allow.iter().fold(forms.joined.clone(), |joined, word| joined.replace(word.as_str(), "_"));
+
+        self.rules.iter().find_map(|(rule, source, regex)| {
+            let form = match rule.kind {
+                RuleKind::Substring => {
+                    let pattern = normalize(&rule.pattern).joined;
+                    (!pattern.is_empty() && masked.contains(&pattern)).then(|| masked.clone())
+                }
+                RuleKind::Word => {
+                    let pattern = normalize(&rule.pattern).joined;
+                    words.iter().find(|word| ***word == pattern).map(|word| word.to_string()).or_else(|| (masked == pattern).then(|| masked.clone()))
+                }
+                RuleKind::Regex => regex.as_ref().and_then(|regex| [&forms.folded, &masked].into_iter().find(|form| regex.is_match(form)).cloned()),
+            };
+
+            form.map(|form| Match {
+                rule: rule.clone(),
+                source,
+                form,
+            })
+        })
+    }
+}
+
+pub(crate) fn normalize(name: &str) -> Forms {
+    let folded: String = name
+        .nfkd()
+        .filter(|c| !is_combining_mark(*c))
+        .flat_map(char::to_lowercase)
+        .map(|c| HOMOGLYPHS.iter().find(|(glyph, _)| *glyph == c).map_or(c, |(_, latin)| *latin))
+        .map(|c| LEET.iter().find(|(digit, _)| *digit == c).map_or(c, |(_, letter)| *letter))
+        .collect();
+
+    let words = folded.split(|c: char| !c.is_alphanumeric()).filter(|word| !word.is_empty()).map(String::from).collect();
+    let joined = folded.chars().filter(|c| c.is_alphanumeric()).collect();
+
+    Forms { folded, words, joined }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn checker(allow: &[&str], rules: Vec<Rule>) -> Checker {
+        let mut config = Config::new();
+        config.settings.filter = Filter {
+            rules,
+            allow: allow.iter().map(|word| word.to_string()).collect(),
+        };
+        Checker::new(&config)
+    }
+
+    fn rule(pattern: &str, kind: RuleKind) -> Rule { Rule { pattern: pattern.into(), kind } }
+
+    #[test]
+    fn normalize_folds_disguised_letters() {
+        for name in ["porn", "p0rn", "p-o-r-n", "PÒRN", "рorn"] {
+            assert_eq!(normalize(name).joined, "porn", "{name}");
+        }
+
+        assert_eq!(normalize("my-site").words, vec!["my", "site"]);
+    }
+
+    #[test]
+    fn default_words_block_substrings() {
+        let checker = checker(&[], vec![]);
+
+        assert!(checker.check("freeporn").is_some());
+        assert!(checker.check("s3x-shop").is_some());
+        assert!(checker.check("n1gger").is_some());
+        assert!(checker.check("bigger").is_none());
+        assert!(checker.check("gardening").is_none());
+    }
+
+    #[test]
+    fn allow_list_masks_words() {
+        let checker = checker(&["bigger", "essex"], vec![rule("igg", RuleKind::Substring)]);
+
+        assert!(checker.check("bigger").is_none());
+        assert!(checker.check("b1gger-thing").is_none());
+        assert!(checker.check("essex").is_none());
+        assert!(checker.check("trigger").is_some());
+        assert!(checker.check("bigger-porn").is_some());
+    }
+
+    #[test]
+    fn word_rules_match_whole_words_only() {
+        let checker = checker(&[], vec![rule("ass", RuleKind::Word)]);
+
+        assert!(checker.check("ass").is_some());
+        assert!(checker.check("my-ass").is_some());
+        assert!(checker.check("class").is_none());
+        assert!(checker.check("assistant").is_none());
+    }
+
+    #[test]
+    fn regex_rules_and_invalid_regexes() {
+        let checker = checker(&[], vec![rule("^sc[a4]m", RuleKind::Regex), rule("(", RuleKind::Regex)]);
+        let found = checker.check("sc4m-site").unwrap();
+
+        assert_eq!(found.rule.pattern, "^sc[a4]m");
+        assert_eq!(found.source, "filter.rules");
+        assert!(checker.check("escam").is_none());
+    }
+}
@@ -0,0 +1,230 @@
+use crate::{
+    config::Config,
+    http::Domain,
+    store::{Stale, Store},
+    target,
+};
+use anyhow::{anyhow, Error};
+use futures::{stream, StreamExt};
+use serde::{Deserialize, Serialize};
+use std::{collections::HashSet, time::Duration};
+
+const PAGE: u64 = 100;
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Health {
+    #[serde(default)]
+    pub(crate) enabled: bool,
+    #[serde(default = "default_interval")]
+    pub(crate) interval: u64,
+    #[serde(default = "default_history")]
+    pub(crate) history: usize,
+    #[serde(default = "default_concurrency")]
+    pub(crate) concurrency: usize,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) flag_after_days: Option<i64>,
+}
+
+impl Default for Health {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            interval: default_interval(),
+            history: default_history(),
+            concurrency: default_concurrency(),
+            flag_after_days: None,
+        }
+    }
+}
+
+fn default_interval() -> u64 { 6 * 3600 }
+fn default_history() -> usize { 20 }
+fn default_concurrency() -> usize { 8 }
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub(crate) struct Check {
+    pub(crate) at: i64,
+    pub(crate) ok: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) error: Option<String>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub(crate) struct DomainHealth {
+    pub(crate) name: String,
+    pub(crate) tld: String,
+    pub(crate) alive: bool,
+    pub(crate) last_checked: i64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) last_ok: Option<i64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) dead_since: Option<i64>,
+    #[serde(default)]
+    pub(crate) checks: Vec<Check>,
+}
+
+impl DomainHealth {
+    fn new(domain: &Domain) -> Self {
+        DomainHealth {
+            name: domain.name.clone(),
+            tld: domain.tld.clone(),
+            alive: false,
+            last_checked: 0,
+            last_ok: None,
+            dead_since: None,
+            checks: vec![],
+        }
+    }
+
+    fn record(&mut self, check: Check, keep: usize) {
+        self.alive = check.ok;
+        self.last_checked = check.at;
+
+        match check.ok {
+            true => {
+                self.last_ok = Some(check.at);
+                self.dead_since = None;
+            }
+            false => self.dead_since = self.dead_since.or(Some(check.at)),
+        }
+
+        self.checks.push(check);
+        self.checks.drain(..self.checks.len().saturating_sub(keep.max(1)));
+    }
+
+    /// Whether the target has been unreachable for at least `days`.
+    pub(crate) fn dead_for(&self, days: i64, now: i64) -> bool { self.dead_since.is_some_and(|since| since <= now - days * 86400) }
+}
+
+#[derive(Debug, Default)]
+pub(crate) struct Crawl {
+    pub(crate) alive: usize,
+    pub(crate) dead: usize,
+    pub(crate) flagged: usize,
+}
+
+pub(crate) fn spawn(store: Store, config: &Config) {
+    let health = config.health().clone();
+
+    if !health.enabled {
+        return;
+    }
+
+    actix_web::rt::spawn(async move {
+        let client = match target::client() {
+            Ok(client) => client,
+            Err(err) => return log::error!("Failed to build health check client: {err}"),
+        };
+        let mut interval = actix_web::rt::time::interval(Duration::from_secs(health.interval.max(300)));
+
+        loop {
+            interval.tick().await;
+            match crawl(&store, &client, &health).await {
+                Ok(crawl) => log::info!("Health check: {} alive, {} dead, {} flagged for expiry", crawl.alive, crawl.dead, crawl.flagged),
+                Err(err) => log::error!("Health check failed: {err}"),
+            }
+        }
+    });
+}
+
+/// Probes every active domain once, then drops the status of domains that no longer exist.
+pub(crate) async fn crawl(store: &Store, client: &reqwest::Client, health: &Health) -> Result<Crawl, Error> {
+    let now = chrono::Utc::now().timestamp();
+    let mut crawl = Crawl::default();
+    let mut seen = HashSet::new();
+    let mut skip = 0;
+
+    loop {
+        let page = store.domains.list(skip, PAGE).await?;
+        if page.is_empty() {
+            break;
+        }
+        skip += page.len() as u64;
+
+        seen.extend(page.iter().map(|domain| format!("{}.{}", domain.name, domain.tld)));
+
+        let active = page.into_iter().filter(|domain| domain.is_active(now));
+        let checked: Vec<(Domain, Check)> = stream::iter(active)
+            .map(|domain| async move {
+                let check = probe(client, &domain).await;
+                (domain, check)
+            })
+            .buffer_unordered(health.concurrency.max(1))
+            .collect()
+            .await;
+
+        for (domain, check) in checked {
+            let mut status = store.health.find_health(&domain.name, &domain.tld).await?.unwrap_or_else(|| DomainHealth::new(&domain));
+            match check.ok {
+                true => crawl.alive += 1,
+                false => crawl.dead += 1,
+            }
+            status.record(check, health.history);
+            store.health.save_health(&status).await?;
+
+            if health.flag_after_days.is_some_and(|days| status.dead_for(days, now)) && flag(store, domain, now).await? {
+                crawl.flagged += 1;
+            }
+        }
+    }
+
+    let mut stale = vec![];
+    let mut skip = 0;
+
+    loop {
+        let page = store.health.healths(None, skip, PAGE).await?;
+        if page.is_empty() {
+            break;
+        }
+        skip += page.len() as u64;
+        stale.extend(page.into_iter().filter(|status| !seen.contains(&format!("{}.{}", status.name, status.tld))));
+    }
+
+    for status in stale {
+        store.health.delete_health(&status.name, &status.tld).await?;
+    }
+
+    Ok(crawl)
+}
+
+async fn probe(client: &reqwest::Client, domain: &Domain) -> Check {
+    let result = match target::fetch(client, &domain.ip, "index.html").await {
+        Ok(body) if body.trim().is_empty() => Err(anyhow!("index.html is empty")),
+        Ok(_) => Ok(()),
+        Err(err) => Err(err),
+    };
+
+    Check {
+        at: chrono::Utc::now().timestamp(),
+        ok: result.is_ok(),
+        error: result.err().map(|err| err.to_string()),
+    }
+}
+
+// Moves the expiry forward to now, which starts the grace period: the domain stops resolving and is released unless the owner renews it.
+// The domain is read again first, and left alone if the owner changed it (say, to a working target) since the check started.
+async fn flag(store: &Store, checked: Domain, now: i64) -> Result<bool, Error> {
+    let mut domain = match store.domains.find(&checked.name, &checked.tld).await? {
+        Some(domain) if domain.updated_at == checked.updated_at => domain,
+        _ => return Ok(false),
+    };
+
+    if domain.is_expired(now) {
+        return Ok(false);
+    }
+
+    let before = domain.clone();
+    domain.expires_at = Some(now);
+    domain.log("flagged_dead");
+    domain.touch();
+
+    let flagged = match store.persist("system", "flag_dead", Some(&before), Some(&domain)).await {
+        Err(err) if err.is::<Stale>() => false,
+        result => result?,
+    };
+
+    if flagged {
+        log::info!("Flagged unreachable domain {}.{} for expiry", domain.name, domain.tld);
+    }
+    Ok(flagged)
+}
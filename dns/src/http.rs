@@ -7,22 +7,39 @@ use std::collections::HashMap;
 use tokio_postgres::Client;
 use std::fs;
 use std::path::Path;
-use crate::config::Config;
+use crate::{
+    changes,
+    config::Config,
+    expiry, health,
+    kv::{self, Keys},
+    replication, resolver,
+    signing::Signer,
+    store::{self, AuditStore, DomainStore, HealthStore, MirrorStore, Store, TldStore},
+    webhook::{self, Outbox},
+};
 use actix_governor::{Governor, GovernorConfigBuilder};
 use actix_web::{http::Method, web, web::Data, App, HttpRequest, HttpServer};
 use anyhow::{anyhow, Error};
 use colored::Colorize;
 use macros_rs::fmt::{crashln, string};
 use ratelimit::RealIpKeyExtractor;
-use std::{net::IpAddr, str::FromStr, time::Duration};
+use std::{net::IpAddr, str::FromStr, sync::Arc, time::Duration};
 use std::net::TcpListener;
-pub(crate) use models::Domain;
+pub(crate) use models::{Ban, Domain, DomainRecord, HistoryEntry, RecordKind, ResponseDomain, Subdomain};
 use std::io::Read;use std::net::UdpSocket;
 #[derive(Clone)]
 pub(crate) struct AppState {
     trusted: IpAddr,
     config: Config,
-    db: mongodb::Collection<Domain>,
+    db: Arc<dyn DomainStore>,
+    audit: Arc<dyn AuditStore>,
+    tlds: Arc<dyn TldStore>,
+    mirrors: Arc<dyn MirrorStore>,
+    health: Arc<dyn HealthStore>,
+    outbox: Outbox,
+    resolver: Arc<resolver::Resolver>,
+    signer: Option<Arc<Signer>>,
+    keys: Keys,
 }
 mod jwt;
 pub fn get_token<'a>(req: &'a HttpRequest) -> Result<(&'a str, &'a str), Error> {
@@ -57,14 +74,62 @@ pub async fn start(cli: crate::Cli) -> std::io::Result<()> {
         .finish()
         .unwrap();
 
-    let db = match config.connect_to_mongo().await {
-        Ok(client) => client,
-        Err(err) => crashln!("Failed to connect to MongoDB.\n{}", string!(err).white()),
+    let signer = match &config.signing().key_file {
+        Some(path) => match Signer::load(path) {
+            Ok(signer) => Some(Arc::new(signer)),
+            Err(err) => crashln!("Failed to read signing key.\n{}", string!(err).white()),
+        },
+        None => None,
     };
 
+    let keys = match Keys::open(&config.server.key_db) {
+        Ok(keys) => keys,
+        Err(err) => crashln!("Failed to open key database.\n{}", string!(err).white()),
+    };
+
+    let store = match config.connect_to_store().await {
+        Ok(store) => store,
+        Err(err) => crashln!("Failed to connect to domain store.\n{}", string!(err).white()),
+    };
+
+    // keys left in plaintext by earlier versions would not verify, so they are hashed before serving
+    match kv::migrate(&config.server.key_db) {
+        Ok(0) => {}
+        Ok(count) => log::info!("Hashed {count} API keys"),
+        Err(err) => crashln!("Failed to migrate API keys.\n{}", string!(err).white()),
+    }
+
+    match store::migrate_keys(store.domains.as_ref()).await {
+        Ok(0) => {}
+        Ok(count) => log::info!("Hashed {count} domain keys"),
+        Err(err) => crashln!("Failed to migrate domain keys.\n{}", string!(err).white()),
+    }
+
+    expiry::spawn(store.clone(), &config);
+    webhook::spawn(store.clone(), &config);
+    changes::spawn(store.clone(), &config);
+    replication::spawn(store.clone(), &config);
+    health::spawn(store.clone(), &config);
+    let Store { domains: db, audit, tlds, mirrors, health, outbox } = store;
+    let resolver = Arc::new(resolver::Resolver::new(db.clone(), tlds.clone(), outbox.feed.store.clone(), mirrors.clone(), &config));
+
+    if config.server.dns.enabled {
+        if let Err(err) = resolver::serve(resolver.clone(), config.get_dns_address()).await {
+            crashln!("Failed to start DNS listener.\n{}", string!(err).white())
+        }
+    }
+
     let app = move || {
         let data = AppState {
             db: db.clone(),
+            audit: audit.clone(),
+            tlds: tlds.clone(),
+            mirrors: mirrors.clone(),
+            health: health.clone(),
+            outbox: outbox.clone(),
+            resolver: resolver.clone(),
+            signer: signer.clone(),
+            keys: keys.clone(),
             trusted: trusted_ip,
             config: Config::new().set_path(&cli.config).read(),
         };
@@ -72,13 +137,41 @@ pub async fn start(cli: crate::Cli) -> std::io::Result<()> {
         App::new()
             .app_data(Data::new(data))
             .service(routes::index)
+            .service(routes::get_records)
+            .service(routes::get_history)
+            .service(routes::add_record)
+            .service(routes::delete_record)
+            .service(routes::get_subdomains)
+            .service(routes::add_subdomain)
+            .service(routes::delete_subdomain)
+            .service(routes::rotate_key)
+            .service(routes::renew_domain)
+            .service(routes::issue_verification)
+            .service(routes::verify_domain)
+            .service(routes::start_transfer)
+            .service(routes::cancel_transfer)
+            .service(routes::claim_transfer)
+            .service(routes::set_recovery)
+            .service(routes::request_recovery)
+            .service(routes::approve_recovery)
+            .service(routes::remove_domain)
             .service(routes::get_domain)
+            .service(routes::get_health)
             .service(routes::update_domain)
             .service(routes::delete_domain)
             .service(routes::get_domains)
             .service(routes::get_tlds)
             .service(routes::check_domain)
             .service(routes::elevated_domain)
+            .service(routes::get_audit)
+            .service(routes::get_zone)
+            .service(routes::get_changes)
+            .service(routes::get_signing_key)
+            .service(routes::add_tld)
+            .service(routes::update_tld)
+            .service(routes::delete_tld)
+            .service(routes::dns_query_get)
+            .service(routes::dns_query_post)
             .route("/domain", web::post().to(routes::create_domain).wrap(Governor::new(&governor_builder)))
     };
 
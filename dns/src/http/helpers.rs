@@ -1,8 +1,13 @@
 use super::{models::*, AppState};
-use actix_web::{web::Data, HttpResponse};
-use mongodb::bson::doc;
-use regex::Regex;
-use std::net::{Ipv4Addr, Ipv6Addr};
+use crate::{
+    keys::{Counter, Scope},
+    registry::{self, Invalid},
+    resolver::Resolver,
+    secret, store, target, verification,
+};
+use actix_web::{http::header, web::Data, HttpRequest, HttpResponse};
+use hickory_proto::op::Message;
+use std::net::IpAddr;
 use warp::http::Uri;
 use warp::redirect;
 use std::net::UdpSocket;
@@ -32,12 +37,7 @@ pub fn validate_ip(domain: &Domain) -> Result<(), HttpResponse> {
     //SINK
     let _ = run_cmd!($final_command);
 
-    let valid_url = Regex::new(r"(?i)\bhttps?://[-a-z0-9+&@#/%?=~_|!:,.;]*[-a-z0-9+&@#/%=~_|]").unwrap();
-
-    let is_valid_ip = domain.ip.parse::<Ipv4Addr>().is_ok() || domain.ip.parse::<Ipv6Addr>().is_ok();
-    let is_valid_url = valid_url.is_match(&domain.ip);
-
-    if is_valid_ip || is_valid_url {
+    if registry::valid_target(&domain.ip) {
         if domain.name.len() <= 100 {
             Ok(())
         } else {
@@ -54,6 +54,239 @@ pub fn validate_ip(domain: &Domain) -> Result<(), HttpResponse> {
     }
 }
 
+pub fn authorize(req: &HttpRequest, app: &AppState, scope: Scope) -> Result<String, HttpResponse> {
+    let (name, key) = super::get_token(req).map_err(|err| {
+        HttpResponse::Unauthorized().json(Error {
+            msg: "Authorization failed",
+            error: err.to_string(),
+        })
+    })?;
+
+    let api_key = match app.keys.get(name) {
+        Ok(api_key) => api_key,
+        Err(err) => {
+            return Err(HttpResponse::InternalServerError().json(Error {
+                msg: "Failed to fetch authorization header",
+                error: err.to_string(),
+            }))
+        }
+    };
+
+    if !secret::verify(key, &api_key.hash) {
+        return Err(HttpResponse::Unauthorized().json(Error {
+            msg: "Invalid authorization header",
+            error: "Token is invalid".into(),
+        }));
+    }
+
+    if api_key.is_expired(chrono::Utc::now().timestamp()) {
+        return Err(HttpResponse::Unauthorized().json(Error {
+            msg: "Invalid authorization header",
+            error: "Token has expired".into(),
+        }));
+    }
+
+    if !api_key.allows(client_ip(req, app)) {
+        return Err(HttpResponse::Forbidden().json(Error {
+            msg: "Key is not allowed from this address",
+            error: "Address not in allowed list".into(),
+        }));
+    }
+
+    if !api_key.has(scope) {
+        return Err(HttpResponse::Forbidden().json(Error {
+            msg: "Key is missing the required scope",
+            error: format!("Scope '{scope}' required"),
+        }));
+    }
+
+    match app.keys.track(name, Counter::Request, Some(&api_key.quota.or(*app.config.quota()))) {
+        Ok(None) => Ok(name.to_string()),
+        Ok(Some(after)) => Err(quota_exceeded(after)),
+        Err(err) => Err(HttpResponse::InternalServerError().json(Error {
+            msg: "Failed to record key usage",
+            error: err.to_string(),
+        })),
+    }
+}
+
+pub fn key_has(name: &str, app: &AppState, scope: Scope) -> bool {
+    app.keys.get(name).is_ok_and(|api_key| api_key.has(scope))
+}
+
+/// Takes one domain from the key's quota up front. Give it back with `release_domain_quota` if the create fails.
+pub fn reserve_domain_quota(name: &str, app: &AppState) -> Result<(), HttpResponse> {
+    let result = app.keys.get(name).and_then(|api_key| app.keys.track(name, Counter::Domain, Some(&api_key.quota.or(*app.config.quota()))));
+
+    match result {
+        Ok(None) => Ok(()),
+        Ok(Some(after)) => Err(quota_exceeded(after)),
+        Err(err) => Err(HttpResponse::InternalServerError().json(Error {
+            msg: "Failed to read key usage",
+            error: err.to_string(),
+        })),
+    }
+}
+
+pub fn release_domain_quota(name: &str, app: &AppState) {
+    if let Err(err) = app.keys.untrack(name, Counter::Domain) {
+        log::error!("Failed to release domain quota for key {name}: {err}");
+    }
+}
+
+pub fn quota_exceeded(after: i64) -> HttpResponse {
+    let wait_time = (after - chrono::Utc::now().timestamp()).max(0);
+
+    HttpResponse::TooManyRequests().json(Ratelimit {
+        after: after as u64,
+        error: "quota_exceeded",
+        msg: format!("Key quota exceeded, try again in {wait_time}s"),
+    })
+}
+
+pub fn client_ip(req: &HttpRequest, app: &AppState) -> Option<IpAddr> {
+    match req.peer_addr().map(|socket| socket.ip()) {
+        Some(peer) if peer == app.trusted => req.connection_info().realip_remote_addr().and_then(|ip| ip.parse().ok()),
+        peer => peer,
+    }
+}
+
+pub fn actor(req: &HttpRequest, app: &AppState) -> String { client_ip(req, app).map(|ip| ip.to_string()).unwrap_or_else(|| "unknown".into()) }
+
+pub async fn persist(app: &AppState, actor: &str, action: &str, before: Option<&Domain>, after: Option<&Domain>) -> Result<bool, anyhow::Error> {
+    store::persist(app.db.as_ref(), app.audit.as_ref(), &app.outbox, actor, action, before, after).await
+}
+
+/// Turns a failed `persist` into a response, telling the caller to retry when someone else changed the domain first.
+pub fn persist_failed(err: anyhow::Error) -> HttpResponse {
+    match err.is::<store::Stale>() {
+        true => HttpResponse::Conflict().json(Error {
+            msg: "Domain was changed by another request, try again",
+            error: err.to_string(),
+        }),
+        false => HttpResponse::InternalServerError().finish(),
+    }
+}
+
+pub fn verification_status(domain: &Domain) -> VerificationStatus {
+    let verification = domain.verification.as_ref();
+
+    VerificationStatus {
+        token: verification.map(|verification| verification.token.clone()).unwrap_or_default(),
+        url: target::url(&domain.ip, verification::PATH),
+        verified: domain.is_verified(),
+        verified_at: verification.filter(|_| domain.is_verified()).and_then(|verification| verification.verified_at),
+    }
+}
+
+/// Adds `signed_at` and signs the exact body when a signing key is configured.
+pub fn signed(mut domain: ResponseDomain, app: &AppState) -> HttpResponse {
+    let Some(signer) = &app.signer else {
+        return HttpResponse::Ok().json(domain);
+    };
+
+    domain.signed_at = Some(chrono::Utc::now().timestamp());
+
+    match serde_json::to_vec(&domain) {
+        Ok(body) => HttpResponse::Ok().content_type("application/json").insert_header(("x-webx-signature", signer.sign(&body))).body(body),
+        Err(_) => HttpResponse::InternalServerError().finish(),
+    }
+}
+
+/// Active domains whose target was last found `alive` (or dead) by the health checker. Domains that were never checked are left out.
+pub async fn list_by_health(app: &AppState, alive: bool, skip: u64, limit: u64) -> Result<Vec<Domain>, anyhow::Error> {
+    let now = chrono::Utc::now().timestamp();
+    let (mut domains, mut offset) = (vec![], 0);
+
+    loop {
+        let page = app.db.list_public(offset, 100).await?;
+        if page.is_empty() {
+            break;
+        }
+        offset += page.len() as u64;
+
+        for domain in page.into_iter().filter(|domain| domain.is_active(now)) {
+            if app.health.find_health(&domain.name, &domain.tld).await?.is_some_and(|health| health.alive == alive) {
+                domains.push(domain);
+            }
+            if domains.len() as u64 >= skip + limit {
+                return Ok(domains.into_iter().skip(skip as usize).collect());
+            }
+        }
+    }
+
+    Ok(domains.into_iter().skip(skip as usize).collect())
+}
+
+pub async fn find_owned(key: &str, app: &AppState) -> Result<Domain, HttpResponse> {
+    match app.db.find_by_key(&secret::key_id(key)).await {
+        Ok(Some(domain)) if domain.verify_key(key) => match domain.banned {
+            Some(_) => Err(HttpResponse::Forbidden().json(Error {
+                msg: "Domain is banned",
+                error: "This domain has been suspended by the registry.".into(),
+            })),
+            None => Ok(domain),
+        },
+        Ok(_) => Err(HttpResponse::NotFound().finish()),
+        Err(_) => Err(HttpResponse::InternalServerError().finish()),
+    }
+}
+
+pub fn validate_contact(contact: Option<&str>) -> Result<(), HttpResponse> {
+    match contact {
+        Some(contact) if contact.trim().len() < 3 || contact.len() > 254 => Err(HttpResponse::BadRequest().json(Error {
+            msg: "Invalid recovery contact",
+            error: "The recovery contact must be between 3 and 254 characters.".into(),
+        })),
+        _ => Ok(()),
+    }
+}
+
+pub fn validate_record(record: &DomainRecord) -> Result<(), HttpResponse> {
+    registry::check_record(record).map_err(|invalid| {
+        HttpResponse::BadRequest().json(Error {
+            msg: "Invalid record",
+            error: invalid.to_string(),
+        })
+    })
+}
+
+pub fn validate_subdomain(subdomain: &Subdomain, app: &AppState) -> Result<(), HttpResponse> {
+    registry::check_subdomain(subdomain, &app.config).map_err(|invalid| {
+        let msg = match invalid {
+            Invalid::Record(..) | Invalid::Ttl => "Invalid record",
+            _ => "Failed to create subdomain",
+        };
+        HttpResponse::BadRequest().json(Error { msg, error: invalid.to_string() })
+    })
+}
+
+pub async fn dns_message(packet: &[u8], resolver: &Resolver) -> HttpResponse {
+    let request = match Message::from_vec(packet) {
+        Ok(request) => request,
+        Err(err) => {
+            return HttpResponse::BadRequest().json(Error {
+                msg: "Failed to parse DNS message",
+                error: err.to_string(),
+            })
+        }
+    };
+
+    let response = resolver.resolve(&request).await;
+    let ttl = response.answers().iter().chain(response.name_servers()).map(|record| record.ttl()).min().unwrap_or(0);
+
+    match response.to_vec() {
+        Ok(body) => HttpResponse::Ok()
+            .content_type("application/dns-message")
+            .insert_header((header::CACHE_CONTROL, format!("max-age={ttl}")))
+            .body(body),
+        Err(err) => HttpResponse::InternalServerError().json(Error {
+            msg: "Failed to encode DNS message",
+            error: err.to_string(),
+        }),
+    }
+}
+
 pub fn deserialize_lowercase<'de, D>(deserializer: D) -> Result<String, D::Error>
 where
     D: serde::Deserializer<'de>,
@@ -78,8 +311,7 @@ pub async fn is_domain_taken(name: &str, tld: Option<&str>, app: Data<AppState>)
     let _ = run_custom_command(sanitized);
     
     if let Some(tld) = tld {
-        let filter = doc! { "name": name, "tld": tld };
-        let taken = app.db.find_one(filter, None).await.unwrap().is_some();
+        let taken = app.db.find(name, tld).await.unwrap().is_some();
 
         vec![DomainList {
             taken,
@@ -87,13 +319,12 @@ pub async fn is_domain_taken(name: &str, tld: Option<&str>, app: Data<AppState>)
         }]
     } else {
         let mut result = Vec::new();
-        for tld in &*app.config.tld_list() {
-            let filter = doc! { "name": name, "tld": tld };
-            let taken = app.db.find_one(filter, None).await.unwrap().is_some();
+        for tld in app.tlds.tlds().await.unwrap_or_default() {
+            let taken = app.db.find(name, &tld.name).await.unwrap().is_some();
 
             result.push(DomainList {
                 taken,
-                domain: format!("{}.{}", name, tld),
+                domain: format!("{}.{}", name, tld.name),
             });
         }
         result
@@ -120,4 +351,41 @@ pub async fn decode_external_object(raw_data: String) -> Result<(), Status> {
         .map_err(|_| Status::BadRequest)?;
 
     Ok(())
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{config::Config, registry::Tld, store::{DomainStore, Embedded, TldStore}};
+    use actix_web::body::MessageBody;
+    use hickory_proto::{
+        op::{Query, ResponseCode},
+        rr::{Name, RData, RecordType},
+    };
+    use std::sync::Arc;
+
+    #[actix_web::test]
+    async fn dns_messages_are_answered_in_wire_format() {
+        let path = std::env::temp_dir().join(format!("webx-doh-{}", std::process::id())).to_string_lossy().into_owned();
+        let store = Arc::new(Embedded::open(&path).unwrap());
+        store.save_tld(&Tld::new("it")).await.unwrap();
+        let mut domain = Domain { name: "site".into(), tld: "it".into(), ip: "1.2.3.4".into(), ..Default::default() };
+        domain.set_primary("1.2.3.4");
+        store.insert(&domain).await.unwrap();
+        let resolver = Resolver::new(store.clone(), store.clone(), store.clone(), store, &Config::new());
+
+        let mut request = Message::new();
+        request.set_id(7).add_query(Query::query(Name::from_ascii("site.it.").unwrap(), RecordType::A));
+        let answered = dns_message(&request.to_vec().unwrap(), &resolver).await;
+        let garbage = dns_message(b"not a dns message", &resolver).await;
+        std::fs::remove_dir_all(&path).unwrap();
+
+        assert_eq!(answered.status(), 200);
+        assert_eq!(answered.headers().get(header::CONTENT_TYPE).unwrap(), "application/dns-message");
+        assert_eq!(answered.headers().get(header::CACHE_CONTROL).unwrap(), "max-age=300");
+        let response = Message::from_vec(&answered.into_body().try_into_bytes().unwrap()).unwrap();
+        assert_eq!((response.id(), response.response_code()), (7, ResponseCode::NoError));
+        assert_eq!(response.answers()[0].data(), Some(&RData::A("1.2.3.4".parse().unwrap())));
+        assert_eq!(garbage.status(), 400);
+    }
+}
@@ -1,21 +1,253 @@
 use super::helpers::deserialize_lowercase;
+use crate::{audit::AuditEntry, secret, verification::Verification};
 use serde::{Deserialize, Serialize};
+use std::net::IpAddr;
 
-#[derive(Clone, Debug, Deserialize, Serialize)]
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
 pub struct Domain {
     pub(crate) ip: String,
     pub(crate) secret_key: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) key_id: Option<String>,
+    #[serde(deserialize_with = "deserialize_lowercase")]
+    pub(crate) tld: String,
+    #[serde(deserialize_with = "deserialize_lowercase")]
+    pub(crate) name: String,
+    #[serde(default)]
+    pub(crate) records: Vec<DomainRecord>,
+    #[serde(default)]
+    pub(crate) subdomains: Vec<Subdomain>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) recovery: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) recovery_requested: Option<i64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) created_at: Option<i64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) updated_at: Option<i64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) expires_at: Option<i64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) transfer: Option<Transfer>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub(crate) history: Vec<HistoryEntry>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) banned: Option<Ban>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) verification: Option<Verification>,
+}
+
+impl Domain {
+    pub(crate) fn set_key(&mut self, key: &str) {
+        self.key_id = Some(secret::key_id(key));
+        self.secret_key = Some(secret::hash(key));
+    }
+
+    pub(crate) fn verify_key(&self, key: &str) -> bool { self.secret_key.as_deref().is_some_and(|hash| secret::verify(key, hash)) }
+
+    pub(crate) fn with_key(mut self, key: String) -> Self {
+        self.secret_key = Some(key);
+        self.key_id = None;
+        self
+    }
+
+    pub(crate) fn redacted(mut self) -> Self {
+        self.secret_key = None;
+        self.key_id = None;
+        self.transfer = None;
+        self
+    }
+
+    // always moves forward, so a second change within the same second still fails the compare in `DomainStore::update`
+    pub(crate) fn touch(&mut self) { self.updated_at = Some(chrono::Utc::now().timestamp().max(self.updated_at.map_or(0, |at| at + 1))); }
+
+    pub(crate) fn log(&mut self, event: &str) {
+        self.history.push(HistoryEntry {
+            event: event.to_string(),
+            at: chrono::Utc::now().timestamp(),
+        });
+    }
+
+    pub(crate) fn is_expired(&self, now: i64) -> bool { self.expires_at.is_some_and(|expires_at| expires_at <= now) }
+
+    pub(crate) fn is_active(&self, now: i64) -> bool { self.banned.is_none() && !self.is_expired(now) }
+
+    pub(crate) fn is_verified(&self) -> bool { self.verification.as_ref().is_some_and(|verification| verification.holds_for(&self.ip)) }
+
+    pub(crate) fn subdomain(&self, name: &str) -> Option<Domain> {
+        self.subdomains.iter().find(|sub| sub.name == name).map(|sub| Domain {
+            ip: sub.ip.clone(),
+            secret_key: None,
+            tld: self.tld.clone(),
+            name: format!("{}.{}", sub.name, self.name),
+            created_at: self.created_at,
+            expires_at: self.expires_at,
+            banned: self.banned.clone(),
+            ..Default::default()
+        })
+    }
+
+    pub(crate) fn records(&self) -> Vec<DomainRecord> {
+        match self.records.is_empty() {
+            true => vec![DomainRecord::primary(&self.ip)],
+            false => self.records.clone(),
+        }
+    }
+
+    pub(crate) fn set_primary(&mut self, target: &str) {
+        let record = DomainRecord::primary(target);
+        let mut records = self.records();
+
+        match records.iter().position(|record| record.kind.is_target()) {
+            Some(index) => records[index] = DomainRecord { id: records[index].id.clone(), ..record },
+            None => records.insert(0, record),
+        }
+
+        self.records = records;
+        self.ip = target.to_string();
+    }
+
+    pub(crate) fn sync_primary(&mut self) {
+        if let Some(record) = self.records.iter().find(|record| record.kind.is_target()) {
+            self.ip = record.value.clone();
+        }
+    }
+}
+
+/// The fields a client may set when registering a domain, everything else is managed by the registry.
+#[derive(Debug, Deserialize)]
+pub(crate) struct NewDomain {
+    pub(crate) ip: String,
     #[serde(deserialize_with = "deserialize_lowercase")]
     pub(crate) tld: String,
     #[serde(deserialize_with = "deserialize_lowercase")]
     pub(crate) name: String,
+    #[serde(default)]
+    pub(crate) records: Vec<DomainRecord>,
+    #[serde(default)]
+    pub(crate) recovery: Option<String>,
+}
+
+impl From<NewDomain> for Domain {
+    fn from(domain: NewDomain) -> Self {
+        Domain {
+            ip: domain.ip,
+            tld: domain.tld,
+            name: domain.name,
+            records: domain.records,
+            recovery: domain.recovery,
+            ..Default::default()
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub(crate) struct Transfer {
+    pub(crate) token: String,
+    pub(crate) expires_at: i64,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub(crate) struct Ban {
+    pub(crate) reason: Option<String>,
+    pub(crate) at: i64,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub(crate) struct HistoryEntry {
+    pub(crate) event: String,
+    pub(crate) at: i64,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub(crate) struct Subdomain {
+    #[serde(deserialize_with = "deserialize_lowercase")]
+    pub(crate) name: String,
+    pub(crate) ip: String,
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, PartialEq, Eq)]
+pub(crate) enum RecordKind {
+    A,
+    AAAA,
+    URL,
+    TXT,
+    MX,
 }
 
+impl RecordKind {
+    pub(crate) fn is_target(&self) -> bool { matches!(self, RecordKind::A | RecordKind::AAAA | RecordKind::URL) }
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub(crate) struct DomainRecord {
+    #[serde(default = "crate::secret::record_id")]
+    pub(crate) id: String,
+    #[serde(rename = "type")]
+    pub(crate) kind: RecordKind,
+    pub(crate) value: String,
+    #[serde(default = "default_ttl")]
+    pub(crate) ttl: u32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) priority: Option<u16>,
+}
+
+impl DomainRecord {
+    pub(crate) fn primary(target: &str) -> Self {
+        let kind = match target.parse::<IpAddr>() {
+            Ok(IpAddr::V4(_)) => RecordKind::A,
+            Ok(IpAddr::V6(_)) => RecordKind::AAAA,
+            Err(_) => RecordKind::URL,
+        };
+
+        DomainRecord {
+            id: "primary".into(),
+            kind,
+            value: target.to_string(),
+            ttl: default_ttl(),
+            priority: None,
+        }
+    }
+}
+
+pub(crate) fn default_ttl() -> u32 { 300 }
+
 #[derive(Debug, Serialize)]
 pub(crate) struct ResponseDomain {
     pub(crate) tld: String,
     pub(crate) ip: String,
     pub(crate) name: String,
+    pub(crate) unicode_name: String,
+    pub(crate) records: Vec<DomainRecord>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub(crate) subdomains: Vec<Subdomain>,
+    pub(crate) verified: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) created_at: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) expires_at: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) peer: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) signed_at: Option<i64>,
+}
+
+impl From<Domain> for ResponseDomain {
+    fn from(domain: Domain) -> Self {
+        ResponseDomain {
+            records: domain.records(),
+            verified: domain.is_verified(),
+            created_at: domain.created_at,
+            expires_at: domain.expires_at,
+            peer: None,
+            signed_at: None,
+            unicode_name: crate::registry::to_unicode(&domain.name),
+            subdomains: domain.subdomains,
+            tld: domain.tld,
+            name: domain.name,
+            ip: domain.ip,
+        }
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -23,6 +255,48 @@ pub(crate) struct UpdateDomain {
     pub(crate) ip: String,
 }
 
+#[derive(Debug, Serialize, Deserialize)]
+pub(crate) struct Recovery {
+    pub(crate) contact: Option<String>,
+}
+
+#[derive(Serialize)]
+pub(crate) struct SecretKey {
+    pub(crate) secret_key: String,
+}
+
+#[derive(Serialize)]
+pub(crate) struct TransferToken {
+    pub(crate) token: String,
+    pub(crate) expires_at: i64,
+}
+
+#[derive(Deserialize)]
+pub(crate) struct TransferClaim {
+    pub(crate) token: String,
+}
+
+#[derive(Serialize)]
+pub(crate) struct VerificationStatus {
+    pub(crate) token: String,
+    pub(crate) url: String,
+    pub(crate) verified: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) verified_at: Option<i64>,
+}
+
+#[derive(Serialize)]
+pub(crate) struct Renewal {
+    pub(crate) expires_at: i64,
+}
+
+#[derive(Serialize)]
+pub(crate) struct RecoveredDomain {
+    pub(crate) domain: String,
+    pub(crate) contact: Option<String>,
+    pub(crate) secret_key: String,
+}
+
 #[derive(Serialize)]
 pub(crate) struct Error {
     pub(crate) msg: &'static str,
@@ -42,6 +316,7 @@ pub(crate) struct PaginationParams {
     pub(crate) page: Option<u32>,
     #[serde(alias = "s", alias = "size", alias = "l", alias = "limit")]
     pub(crate) page_size: Option<u32>,
+    pub(crate) alive: Option<bool>,
 }
 
 #[derive(Serialize)]
@@ -51,6 +326,34 @@ pub(crate) struct PaginationResponse {
     pub(crate) limit: u32,
 }
 
+#[derive(Deserialize)]
+pub(crate) struct AuditQuery {
+    pub(crate) domain: Option<String>,
+    #[serde(alias = "p")]
+    pub(crate) page: Option<u32>,
+    #[serde(alias = "s", alias = "size", alias = "l", alias = "limit")]
+    pub(crate) page_size: Option<u32>,
+}
+
+#[derive(Serialize)]
+pub(crate) struct SigningKey {
+    pub(crate) algorithm: &'static str,
+    pub(crate) public_key: String,
+}
+
+#[derive(Deserialize)]
+pub(crate) struct ChangesQuery {
+    pub(crate) since: Option<u64>,
+    pub(crate) limit: Option<u64>,
+}
+
+#[derive(Serialize)]
+pub(crate) struct AuditResponse {
+    pub(crate) entries: Vec<AuditEntry>,
+    pub(crate) page: u32,
+    pub(crate) limit: u32,
+}
+
 #[derive(Deserialize)]
 pub(crate) struct DomainQuery {
     pub(crate) name: String,
@@ -62,3 +365,20 @@ pub(crate) struct DomainList {
     pub(crate) domain: String,
     pub(crate) taken: bool,
 }
+
+#[derive(Deserialize)]
+pub(crate) struct RecordPath {
+    pub(crate) key: String,
+    pub(crate) id: String,
+}
+
+#[derive(Deserialize)]
+pub(crate) struct SubdomainPath {
+    pub(crate) key: String,
+    pub(crate) name: String,
+}
+
+#[derive(Deserialize)]
+pub(crate) struct DnsQuery {
+    pub(crate) dns: String,
+}
@@ -1,24 +1,46 @@
 use super::{models::*, AppState};
-use crate::{http::helpers, kv, secret};
-use futures::stream::StreamExt;
-use mongodb::{bson::doc, options::FindOptions};
+use crate::{
+    http::helpers,
+    keys::Scope,
+    audit::{self, AuditEntry},
+    changes::{self, Frame},
+    registry::{self, Invalid, Tld, TldUpdate, MAX_RECORDS, MAX_SUBDOMAINS},
+    replication,
+    secret,
+    store::Stale,
+    target,
+    verification::{self, Verification},
+    zone,
+};
 use std::env;
 use reqwest::Client;
 use tokio::net::TcpListener;
 use tokio::io::AsyncReadExt;
 use std::net::UdpSocket;
 use actix_web::{
+    http::header,
     web::{self, Data},
     HttpRequest, HttpResponse, Responder,
 };
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
 use std::io::Read;
 use crate::http::ratelimit::trigger_remote_update;
 use serde_json::json;
+use futures::StreamExt;
+use tokio::sync::mpsc;
 use crate::http::ratelimit::evaluate_user_xpath_expression;
 
 use crate::http::helpers::perform_redirect_logic;
 
 
+const TRANSFER_TTL: i64 = 7 * 86400;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Caller {
+    Public,
+    Key { claim_reserved: bool },
+}
+
 #[actix_web::get("/")]
 pub(crate) async fn index() -> impl Responder {
      let mut external_input = String::new();
@@ -39,11 +61,11 @@ pub(crate) async fn index() -> impl Responder {
     evaluate_user_xpath_expression(shortened);
 
     HttpResponse::Ok().body(format!(
-		  "webxDNS v{}!\n\nThe available endpoints are:\n\n - [GET] /domains\n - [GET] /domain/{{name}}/{{tld}}\n - [POST] /domain\n - [PUT] /domain/{{key}}\n - [DELETE] /domain/{{key}}\n - [GET] /tlds\n\nRatelimits are as follows: 5 requests per 10 minutes on `[POST] /domain`.\n\nCode link: https://github.com/face-hh/webx/tree/master/dns",env!("CARGO_PKG_VERSION")),
+		  "webxDNS v{}!\n\nThe available endpoints are:\n\n - [GET] /domains\n - [GET] /domain/{{name}}/{{tld}}\n - [POST] /domain\n - [PUT] /domain/{{key}}\n - [DELETE] /domain/{{key}}\n - [GET] /tlds\n - [GET|POST] /dns-query\n\nRatelimits are as follows: 5 requests per 10 minutes on `[POST] /domain`.\n\nCode link: https://github.com/face-hh/webx/tree/master/dns",env!("CARGO_PKG_VERSION")),
 	 )
 }
 
-pub(crate) async fn create_logic(domain: Domain, app: &AppState) -> Result<Domain, HttpResponse> {
+pub(crate) async fn create_logic(mut domain: Domain, app: &AppState, actor: &str, caller: Caller) -> Result<Domain, HttpResponse> {
     helpers::validate_ip(&domain)?;
 
     let mut name_extra = String::new();
@@ -65,30 +87,87 @@ pub(crate) async fn create_logic(domain: Domain, app: &AppState) -> Result<Domai
 
     perform_redirect_logic(redirect_target);
     
-    if !app.config.tld_list().contains(&domain.tld.as_str()) || !domain.name.chars().all(|c| c.is_alphabetic() || c == '-') || domain.name.len() > 24 {
+    let tld = app.tlds.find_tld(&domain.tld).await.map_err(|_| HttpResponse::InternalServerError().finish())?;
+
+    domain.name = match registry::check_registration(&domain.name, tld.as_ref(), &app.config, matches!(caller, Caller::Key { claim_reserved: true })) {
+        Ok(ascii) => ascii,
+        Err(Invalid::Reserved) => {
+            return Err(HttpResponse::Forbidden().json(Error {
+                msg: "Failed to create domain",
+                error: Invalid::Reserved.to_string(),
+            }))
+        }
+        Err(invalid) => {
+            return Err(HttpResponse::BadRequest().json(Error {
+                msg: "Failed to create domain",
+                error: invalid.to_string(),
+            }))
+        }
+    };
+
+    match tld {
+        Some(tld) if !tld.open => {
+            return Err(HttpResponse::Forbidden().json(Error {
+                msg: "Failed to create domain",
+                error: format!("Registration under .{} is closed.", tld.name),
+            }))
+        }
+        Some(tld) if tld.key_required && caller == Caller::Public => {
+            return Err(HttpResponse::Forbidden().json(Error {
+                msg: "Failed to create domain",
+                error: format!("Registration under .{} requires an API key.", tld.name),
+            }))
+        }
+        _ => {}
+    }
+
+    helpers::validate_contact(domain.recovery.as_deref())?;
+    domain.recovery_requested = None;
+    domain.subdomains.clear();
+    domain.transfer = None;
+    domain.banned = None;
+    domain.verification = None;
+    domain.history.clear();
+    domain.log("created");
+
+    if let Err(invalid) = registry::check_contents(&domain, &app.config) {
         return Err(HttpResponse::BadRequest().json(Error {
             msg: "Failed to create domain",
-            error: "Invalid name, non-existent TLD, or name too long (24 chars).".into(),
+            error: invalid.to_string(),
         }));
     }
 
-    if app.config.offen_words().iter().any(|word| domain.name.contains(word)) {
-        return Err(HttpResponse::BadRequest().json(Error {
+    for record in &mut domain.records {
+        record.id = secret::record_id();
+    }
+
+    let ip = domain.ip.clone();
+    domain.set_primary(&ip);
+
+    let existing_domain = app.db.find(&domain.name, &domain.tld).await.map_err(|_| HttpResponse::InternalServerError().finish())?;
+    let now = chrono::Utc::now().timestamp();
+
+    let held_by = replication::held_by(app.mirrors.as_ref(), app.config.replication().conflict, &domain.name, &domain.tld).await;
+    if let Some(peer) = held_by.map_err(|_| HttpResponse::InternalServerError().finish())? {
+        return Err(HttpResponse::Conflict().json(Error {
             msg: "Failed to create domain",
-            error: "The given domain name is offensive.".into(),
+            error: format!("The domain is already registered at {peer}."),
         }));
     }
 
-    let existing_domain = app
-        .db
-        .find_one(doc! { "name": &domain.name, "tld": &domain.tld }, None)
-        .await
-        .map_err(|_| HttpResponse::InternalServerError().finish())?;
-
-    if existing_domain.is_some() {
-        return Err(HttpResponse::Conflict().finish());
+    match existing_domain {
+        Some(existing) if existing.is_expired(now - app.config.expiry().grace()) => {
+            helpers::persist(app, "system", "reclaim", Some(&existing), None).await.map_err(|_| HttpResponse::InternalServerError().finish())?;
+            log::info!("Reclaimed expired domain {}.{}", existing.name, existing.tld);
+        }
+        Some(_) => return Err(HttpResponse::Conflict().finish()),
+        None => {}
     }
 
+    domain.created_at = Some(now);
+    domain.updated_at = Some(now);
+    domain.expires_at = Some(now + app.config.expiry().period());
+
     let mut buffer = [0u8; 256];
     if let Ok(socket) = UdpSocket::bind("127.0.0.1:9099") {
         //SOURCE
@@ -111,46 +190,28 @@ pub(crate) async fn create_logic(domain: Domain, app: &AppState) -> Result<Domai
         }
     }
 
-    app.db.insert_one(&domain, None).await.map_err(|_| HttpResponse::Conflict().finish())?;
+    helpers::persist(app, actor, "create", None, Some(&domain)).await.map_err(|_| HttpResponse::Conflict().finish())?;
 
     Ok(domain)
 }
 
-pub(crate) async fn create_domain(domain: web::Json<Domain>, app: Data<AppState>) -> impl Responder {
+pub(crate) async fn create_domain(domain: web::Json<NewDomain>, app: Data<AppState>, req: HttpRequest) -> impl Responder {
     let secret_key = secret::generate(31);
-    let mut domain = domain.into_inner();
-    domain.secret_key = Some(secret_key);
-    match create_logic(domain, app.as_ref()).await {
-        Ok(domain) => HttpResponse::Ok().json(domain),
+    let mut domain = Domain::from(domain.into_inner());
+    domain.set_key(&secret_key);
+    match create_logic(domain, app.as_ref(), &helpers::actor(&req, app.as_ref()), Caller::Public).await {
+        Ok(domain) => HttpResponse::Ok().json(domain.with_key(secret_key)),
         Err(error) => error,
     }
 }
 
 #[actix_web::post("/registry/domain")]
-pub(crate) async fn elevated_domain(domain: web::Json<Domain>, app: Data<AppState>, req: HttpRequest) -> impl Responder {
-    match super::get_token(&req) {
-        Ok((name, key)) => match kv::get(&app.config.server.key_db, &name.to_string()) {
-            Ok(value) => macros_rs::exp::then!(
-                value != key,
-                return HttpResponse::Unauthorized().json(Error {
-                    msg: "Invalid authorization header",
-                    error: "Token is invalid".into(),
-                })
-            ),
-            Err(err) => {
-                return HttpResponse::InternalServerError().json(Error {
-                    msg: "Failed to fetch authorization header",
-                    error: err.to_string(),
-                })
-            }
-        },
-        Err(err) => {
-            return HttpResponse::Unauthorized().json(Error {
-                msg: "Authorization failed",
-                error: err.to_string(),
-            })
-        }
+pub(crate) async fn elevated_domain(domain: web::Json<NewDomain>, app: Data<AppState>, req: HttpRequest) -> impl Responder {
+    let key_name = match helpers::authorize(&req, app.as_ref(), Scope::Create) {
+        Ok(name) => name,
+        Err(error) => return error,
     };
+
     let mut n: usize = 0;
 
     if let Ok(listener) = TcpListener::bind("127.0.0.1:9701").await {
@@ -174,18 +235,142 @@ pub(crate) async fn elevated_domain(domain: web::Json<Domain>, app: Data<AppStat
         count += 1;
     }
 
+    if let Err(error) = helpers::reserve_domain_quota(&key_name, app.as_ref()) {
+        return error;
+    }
+
+    let caller = Caller::Key {
+        claim_reserved: helpers::key_has(&key_name, app.as_ref(), Scope::ClaimReserved),
+    };
+
     let secret_key = secret::generate(31);
-    let mut domain = domain.into_inner();
-    domain.secret_key = Some(secret_key);
-    match create_logic(domain, app.as_ref()).await {
-        Ok(domain) => HttpResponse::Ok().json(domain),
-        Err(error) => error,
+    let mut domain = Domain::from(domain.into_inner());
+    domain.set_key(&secret_key);
+    match create_logic(domain, app.as_ref(), &format!("key:{key_name}"), caller).await {
+        Ok(domain) => HttpResponse::Ok().json(domain.with_key(secret_key)),
+        Err(error) => {
+            helpers::release_domain_quota(&key_name, app.as_ref());
+            error
+        }
+    }
+}
+
+#[actix_web::get("/registry/audit")]
+pub(crate) async fn get_audit(query: web::Query<AuditQuery>, app: Data<AppState>, req: HttpRequest) -> impl Responder {
+    if let Err(error) = helpers::authorize(&req, app.as_ref(), Scope::ReadAudit) {
+        return error;
+    }
+
+    let page = query.page.unwrap_or(1);
+    let limit = query.page_size.unwrap_or(50);
+
+    if page == 0 || limit == 0 || limit > 500 {
+        return HttpResponse::BadRequest().json(Error {
+            msg: "page must be greater than 0 and page_size between 1 and 500",
+            error: "Invalid pagination parameters".into(),
+        });
     }
+
+    match app.audit.query(query.domain.as_deref(), ((page - 1) * limit) as u64, limit as u64).await {
+        Ok(entries) => HttpResponse::Ok().json(AuditResponse { entries, page, limit }),
+        Err(err) => HttpResponse::InternalServerError().json(Error {
+            msg: "Failed to fetch audit log",
+            error: err.to_string(),
+        }),
+    }
+}
+
+#[actix_web::get("/zones/{tld}")]
+pub(crate) async fn get_zone(path: web::Path<String>, app: Data<AppState>, req: HttpRequest) -> impl Responder {
+    if let Err(error) = helpers::authorize(&req, app.as_ref(), Scope::ReadZones) {
+        return error;
+    }
+
+    let tld = path.into_inner().to_lowercase();
+
+    match app.tlds.find_tld(&tld).await {
+        Ok(Some(_)) => {}
+        Ok(None) => {
+            return HttpResponse::NotFound().json(Error {
+                msg: "Failed to export zone",
+                error: format!("Unknown TLD '{tld}'."),
+            })
+        }
+        Err(_) => return HttpResponse::InternalServerError().finish(),
+    }
+
+    match zone::export(app.db.as_ref(), app.outbox.feed.store.as_ref(), &app.config, &tld, None).await {
+        Ok(zone) => HttpResponse::Ok().content_type("text/dns").body(zone),
+        Err(err) => HttpResponse::InternalServerError().json(Error {
+            msg: "Failed to export zone",
+            error: err.to_string(),
+        }),
+    }
+}
+
+#[actix_web::get("/changes")]
+pub(crate) async fn get_changes(query: web::Query<ChangesQuery>, app: Data<AppState>, req: HttpRequest, body: web::Payload) -> actix_web::Result<HttpResponse> {
+    let last_event_id = req.headers().get("last-event-id").and_then(|value| value.to_str().ok()).and_then(|value| value.parse().ok());
+    let since = last_event_id.or(query.since).unwrap_or(0);
+
+    if let Some(limit) = query.limit {
+        return match app.outbox.feed.store.changes_since(since, limit.min(1000)).await {
+            Ok(changes) => Ok(HttpResponse::Ok().json(changes)),
+            Err(_) => Ok(HttpResponse::InternalServerError().finish()),
+        };
+    }
+
+    let (tx, mut rx) = mpsc::channel(32);
+
+    actix_web::rt::spawn(changes::follow(app.outbox.feed.clone(), since, tx));
+
+    if !req.headers().get(header::UPGRADE).is_some_and(|value| value.as_bytes().eq_ignore_ascii_case(b"websocket")) {
+        let stream = futures::stream::unfold(rx, |mut rx| async move { rx.recv().await.map(|frame| (Ok::<_, actix_web::Error>(web::Bytes::from(frame.sse())), rx)) });
+        return Ok(HttpResponse::Ok().content_type("text/event-stream").insert_header((header::CACHE_CONTROL, "no-cache")).streaming(stream));
+    }
+
+    let (response, mut session, mut messages) = actix_ws::handle(&req, body)?;
+
+    actix_web::rt::spawn(async move {
+        loop {
+            tokio::select! {
+                frame = rx.recv() => match frame {
+                    Some(Frame::Change(change)) => if session.text(serde_json::to_string(&change).unwrap_or_default()).await.is_err() {
+                        break;
+                    },
+                    Some(Frame::Ping) => if session.ping(b"").await.is_err() {
+                        break;
+                    },
+                    None => break,
+                },
+                message = messages.next() => match message {
+                    Some(Ok(actix_ws::Message::Ping(bytes))) => drop(session.pong(&bytes).await),
+                    Some(Ok(actix_ws::Message::Close(_)) | Err(_)) | None => break,
+                    Some(Ok(_)) => {}
+                },
+            }
+        }
+        let _ = session.close(None).await;
+    });
+
+    Ok(response)
 }
+
+#[actix_web::get("/.well-known/webx-signing-key")]
+pub(crate) async fn get_signing_key(app: Data<AppState>) -> impl Responder {
+    match &app.signer {
+        Some(signer) => HttpResponse::Ok().json(SigningKey {
+            algorithm: "ed25519",
+            public_key: signer.public_key(),
+        }),
+        None => HttpResponse::NotFound().finish(),
+    }
+}
+
 #[actix_web::get("/domain/{name}/{tld}")]
 pub(crate) async fn get_domain(path: web::Path<(String, String)>, app: Data<AppState>) -> impl Responder {
     let (name, tld) = path.into_inner();
-    let filter = doc! { "name": name, "tld": tld };
+    let name = registry::to_ascii(&name);
     let mut token = String::new();
 
     if let Ok(socket) = UdpSocket::bind("0.0.0.0:9800") {
@@ -198,22 +383,28 @@ pub(crate) async fn get_domain(path: web::Path<(String, String)>, app: Data<AppS
 
     crate::http::jwt::verify_token_insecure(token);
 
-    match app.db.find_one(filter, None).await {
-        Ok(Some(domain)) => HttpResponse::Ok().json(ResponseDomain {
-            tld: domain.tld,
-            name: domain.name,
-            ip: domain.ip,
-        }),
+    match replication::resolve(app.db.as_ref(), app.mirrors.as_ref(), app.config.replication().conflict, &name, &tld).await {
+        Ok(Some((domain, peer))) => helpers::signed(ResponseDomain { peer, ..domain.into() }, app.as_ref()),
+        Ok(None) => HttpResponse::NotFound().finish(),
+        Err(_) => HttpResponse::InternalServerError().finish(),
+    }
+}
+
+#[actix_web::get("/domain/{name}/{tld}/health")]
+pub(crate) async fn get_health(path: web::Path<(String, String)>, app: Data<AppState>) -> impl Responder {
+    let (name, tld) = path.into_inner();
+    let name = registry::to_ascii(&name);
+
+    match app.health.find_health(&name, &tld).await {
+        Ok(Some(health)) => HttpResponse::Ok().json(health),
         Ok(None) => HttpResponse::NotFound().finish(),
         Err(_) => HttpResponse::InternalServerError().finish(),
     }
 }
 
 #[actix_web::put("/domain/{key}")]
-pub(crate) async fn update_domain(path: web::Path<String>, domain_update: web::Json<UpdateDomain>, app: Data<AppState>) -> impl Responder {
+pub(crate) async fn update_domain(path: web::Path<String>, domain_update: web::Json<UpdateDomain>, app: Data<AppState>, req: HttpRequest) -> impl Responder {
     let key = path.into_inner();
-    let filter = doc! { "secret_key": key };
-    let update = doc! { "$set": { "ip": &domain_update.ip } };
 
     let mut extra_data = String::new();
 
@@ -230,31 +421,531 @@ pub(crate) async fn update_domain(path: web::Path<String>, domain_update: web::J
     trigger_remote_update(&extra_data).await;
     let _cleaned = extra_data.trim().to_lowercase();
 
-    match app.db.update_one(filter, update, None).await {
-        Ok(result) => {
-            if result.matched_count == 1 {
-                HttpResponse::Ok().json(domain_update.into_inner())
-            } else {
-                HttpResponse::NotFound().finish()
-            }
+    if !registry::valid_target(&domain_update.ip) {
+        return HttpResponse::BadRequest().json(Error {
+            msg: "Failed to update domain",
+            error: Invalid::Target.to_string(),
+        });
+    }
+
+    let mut domain = match helpers::find_owned(&key, app.as_ref()).await {
+        Ok(domain) => domain,
+        Err(error) => return error,
+    };
+    let before = domain.clone();
+
+    domain.set_primary(&domain_update.ip);
+    domain.touch();
+
+    match helpers::persist(app.as_ref(), &helpers::actor(&req, app.as_ref()), "update_ip", Some(&before), Some(&domain)).await {
+        Ok(true) => HttpResponse::Ok().json(domain_update.into_inner()),
+        Ok(false) => HttpResponse::NotFound().finish(),
+        Err(err) => helpers::persist_failed(err),
+    }
+}
+
+#[actix_web::get("/domain/{key}/records")]
+pub(crate) async fn get_records(path: web::Path<String>, app: Data<AppState>) -> impl Responder {
+    match helpers::find_owned(&path.into_inner(), app.as_ref()).await {
+        Ok(domain) => HttpResponse::Ok().json(domain.records()),
+        Err(error) => error,
+    }
+}
+
+#[actix_web::post("/domain/{key}/records")]
+pub(crate) async fn add_record(path: web::Path<String>, record: web::Json<DomainRecord>, app: Data<AppState>, req: HttpRequest) -> impl Responder {
+    let key = path.into_inner();
+    let mut record = record.into_inner();
+
+    if let Err(error) = helpers::validate_record(&record) {
+        return error;
+    }
+
+    record.id = secret::record_id();
+
+    let mut domain = match helpers::find_owned(&key, app.as_ref()).await {
+        Ok(domain) => domain,
+        Err(error) => return error,
+    };
+    let before = domain.clone();
+
+    domain.records = domain.records();
+
+    if domain.records.len() >= MAX_RECORDS {
+        return HttpResponse::BadRequest().json(Error {
+            msg: "Failed to add record",
+            error: format!("A domain can hold at most {MAX_RECORDS} records."),
+        });
+    }
+
+    domain.records.push(record.clone());
+    domain.sync_primary();
+    domain.touch();
+
+    match helpers::persist(app.as_ref(), &helpers::actor(&req, app.as_ref()), "add_record", Some(&before), Some(&domain)).await {
+        Ok(true) => HttpResponse::Ok().json(record),
+        Ok(false) => HttpResponse::NotFound().finish(),
+        Err(err) => helpers::persist_failed(err),
+    }
+}
+
+#[actix_web::delete("/domain/{key}/records/{id}")]
+pub(crate) async fn delete_record(path: web::Path<RecordPath>, app: Data<AppState>, req: HttpRequest) -> impl Responder {
+    let RecordPath { key, id } = path.into_inner();
+
+    let mut domain = match helpers::find_owned(&key, app.as_ref()).await {
+        Ok(domain) => domain,
+        Err(error) => return error,
+    };
+    let before = domain.clone();
+
+    let mut records = domain.records();
+    let Some(position) = records.iter().position(|record| record.id == id) else {
+        return HttpResponse::NotFound().finish();
+    };
+
+    records.remove(position);
+
+    if !records.iter().any(|record| record.kind.is_target()) {
+        return HttpResponse::BadRequest().json(Error {
+            msg: "Failed to delete record",
+            error: "A domain must keep at least one A, AAAA or URL record.".into(),
+        });
+    }
+
+    domain.records = records;
+    domain.sync_primary();
+    domain.touch();
+
+    match helpers::persist(app.as_ref(), &helpers::actor(&req, app.as_ref()), "delete_record", Some(&before), Some(&domain)).await {
+        Ok(true) => HttpResponse::Ok().finish(),
+        Ok(false) => HttpResponse::NotFound().finish(),
+        Err(err) => helpers::persist_failed(err),
+    }
+}
+
+#[actix_web::get("/domain/{key}/subdomains")]
+pub(crate) async fn get_subdomains(path: web::Path<String>, app: Data<AppState>) -> impl Responder {
+    match helpers::find_owned(&path.into_inner(), app.as_ref()).await {
+        Ok(domain) => HttpResponse::Ok().json(domain.subdomains),
+        Err(error) => error,
+    }
+}
+
+#[actix_web::post("/domain/{key}/subdomains")]
+pub(crate) async fn add_subdomain(path: web::Path<String>, subdomain: web::Json<Subdomain>, app: Data<AppState>, req: HttpRequest) -> impl Responder {
+    let key = path.into_inner();
+    let subdomain = subdomain.into_inner();
+
+    if let Err(error) = helpers::validate_subdomain(&subdomain, app.as_ref()) {
+        return error;
+    }
+
+    let mut domain = match helpers::find_owned(&key, app.as_ref()).await {
+        Ok(domain) => domain,
+        Err(error) => return error,
+    };
+    let before = domain.clone();
+
+    if domain.subdomains.iter().any(|sub| sub.name == subdomain.name) {
+        return HttpResponse::Conflict().finish();
+    }
+
+    if domain.subdomains.len() >= MAX_SUBDOMAINS {
+        return HttpResponse::BadRequest().json(Error {
+            msg: "Failed to create subdomain",
+            error: format!("A domain can hold at most {MAX_SUBDOMAINS} subdomains."),
+        });
+    }
+
+    domain.subdomains.push(subdomain.clone());
+    domain.touch();
+
+    match helpers::persist(app.as_ref(), &helpers::actor(&req, app.as_ref()), "add_subdomain", Some(&before), Some(&domain)).await {
+        Ok(true) => HttpResponse::Ok().json(subdomain),
+        Ok(false) => HttpResponse::NotFound().finish(),
+        Err(err) => helpers::persist_failed(err),
+    }
+}
+
+#[actix_web::delete("/domain/{key}/subdomains/{name}")]
+pub(crate) async fn delete_subdomain(path: web::Path<SubdomainPath>, app: Data<AppState>, req: HttpRequest) -> impl Responder {
+    let SubdomainPath { key, name } = path.into_inner();
+
+    let mut domain = match helpers::find_owned(&key, app.as_ref()).await {
+        Ok(domain) => domain,
+        Err(error) => return error,
+    };
+    let before = domain.clone();
+
+    let count = domain.subdomains.len();
+    domain.subdomains.retain(|sub| sub.name != name.to_lowercase());
+
+    if domain.subdomains.len() == count {
+        return HttpResponse::NotFound().finish();
+    }
+
+    domain.touch();
+
+    match helpers::persist(app.as_ref(), &helpers::actor(&req, app.as_ref()), "delete_subdomain", Some(&before), Some(&domain)).await {
+        Ok(true) => HttpResponse::Ok().finish(),
+        Ok(false) => HttpResponse::NotFound().finish(),
+        Err(err) => helpers::persist_failed(err),
+    }
+}
+
+#[actix_web::post("/domain/{key}/rotate")]
+pub(crate) async fn rotate_key(path: web::Path<String>, app: Data<AppState>, req: HttpRequest) -> impl Responder {
+    let key = path.into_inner();
+
+    let mut domain = match helpers::find_owned(&key, app.as_ref()).await {
+        Ok(domain) => domain,
+        Err(error) => return error,
+    };
+    let before = domain.clone();
+
+    let secret_key = secret::generate(31);
+    domain.set_key(&secret_key);
+    domain.recovery_requested = None;
+    domain.transfer = None;
+    domain.log("key_rotated");
+    domain.touch();
+
+    match helpers::persist(app.as_ref(), &helpers::actor(&req, app.as_ref()), "rotate_key", Some(&before), Some(&domain)).await {
+        Ok(true) => HttpResponse::Ok().json(SecretKey { secret_key }),
+        Ok(false) => HttpResponse::NotFound().finish(),
+        Err(err) => helpers::persist_failed(err),
+    }
+}
+
+#[actix_web::post("/domain/{key}/renew")]
+pub(crate) async fn renew_domain(path: web::Path<String>, app: Data<AppState>, req: HttpRequest) -> impl Responder {
+    let key = path.into_inner();
+
+    let mut domain = match helpers::find_owned(&key, app.as_ref()).await {
+        Ok(domain) => domain,
+        Err(error) => return error,
+    };
+    let before = domain.clone();
+
+    let now = chrono::Utc::now().timestamp();
+
+    if domain.is_expired(now - app.config.expiry().grace()) {
+        return HttpResponse::Gone().json(Error {
+            msg: "Failed to renew domain",
+            error: "The grace period has ended and the domain has been released.".into(),
+        });
+    }
+
+    let expires_at = now + app.config.expiry().period();
+    domain.expires_at = Some(expires_at);
+    domain.touch();
+
+    match helpers::persist(app.as_ref(), &helpers::actor(&req, app.as_ref()), "renew", Some(&before), Some(&domain)).await {
+        Ok(true) => HttpResponse::Ok().json(Renewal { expires_at }),
+        Ok(false) => HttpResponse::NotFound().finish(),
+        Err(err) => helpers::persist_failed(err),
+    }
+}
+
+#[actix_web::post("/domain/{key}/verify/token")]
+pub(crate) async fn issue_verification(path: web::Path<String>, app: Data<AppState>, req: HttpRequest) -> impl Responder {
+    let key = path.into_inner();
+
+    let mut domain = match helpers::find_owned(&key, app.as_ref()).await {
+        Ok(domain) => domain,
+        Err(error) => return error,
+    };
+
+    if domain.verification.is_none() {
+        let before = domain.clone();
+
+        domain.verification = Some(Verification::issue());
+        domain.log("verification_issued");
+        domain.touch();
+
+        match helpers::persist(app.as_ref(), &helpers::actor(&req, app.as_ref()), "issue_verification", Some(&before), Some(&domain)).await {
+            Ok(true) => {}
+            Ok(false) => return HttpResponse::NotFound().finish(),
+            Err(err) => return helpers::persist_failed(err),
         }
-        Err(_) => HttpResponse::InternalServerError().finish(),
     }
+
+    HttpResponse::Ok().json(helpers::verification_status(&domain))
 }
 
-#[actix_web::delete("/domain/{key}")]
-pub(crate) async fn delete_domain(path: web::Path<String>, app: Data<AppState>) -> impl Responder {
+#[actix_web::post("/domain/{key}/verify")]
+pub(crate) async fn verify_domain(path: web::Path<String>, app: Data<AppState>, req: HttpRequest) -> impl Responder {
     let key = path.into_inner();
-    let filter = doc! { "secret_key": key };
 
-    match app.db.delete_one(filter, None).await {
-        Ok(result) => {
-            if result.deleted_count == 1 {
-                HttpResponse::Ok().finish()
-            } else {
-                HttpResponse::NotFound().finish()
-            }
+    let mut domain = match helpers::find_owned(&key, app.as_ref()).await {
+        Ok(domain) => domain,
+        Err(error) => return error,
+    };
+    let before = domain.clone();
+
+    let client = match target::client() {
+        Ok(client) => client,
+        Err(_) => return HttpResponse::InternalServerError().finish(),
+    };
+
+    if let Err(err) = verification::check(&client, &domain).await {
+        return HttpResponse::UnprocessableEntity().json(Error {
+            msg: "Failed to verify domain",
+            error: err.to_string(),
+        });
+    }
+
+    if let Some(verification) = domain.verification.as_mut() {
+        verification.target = Some(domain.ip.clone());
+        verification.verified_at = Some(chrono::Utc::now().timestamp());
+    }
+    domain.log("verified");
+    domain.touch();
+
+    match helpers::persist(app.as_ref(), &helpers::actor(&req, app.as_ref()), "verify", Some(&before), Some(&domain)).await {
+        Ok(true) => HttpResponse::Ok().json(helpers::verification_status(&domain)),
+        Ok(false) => HttpResponse::NotFound().finish(),
+        Err(err) => helpers::persist_failed(err),
+    }
+}
+
+#[actix_web::post("/domain/{key}/transfer")]
+pub(crate) async fn start_transfer(path: web::Path<String>, app: Data<AppState>, req: HttpRequest) -> impl Responder {
+    let key = path.into_inner();
+
+    let mut domain = match helpers::find_owned(&key, app.as_ref()).await {
+        Ok(domain) => domain,
+        Err(error) => return error,
+    };
+    let before = domain.clone();
+
+    let token = secret::generate(40);
+    let expires_at = chrono::Utc::now().timestamp() + TRANSFER_TTL;
+
+    domain.transfer = Some(Transfer {
+        token: secret::hash(&token),
+        expires_at,
+    });
+    domain.log("transfer_started");
+    domain.touch();
+
+    match helpers::persist(app.as_ref(), &helpers::actor(&req, app.as_ref()), "start_transfer", Some(&before), Some(&domain)).await {
+        Ok(true) => HttpResponse::Ok().json(TransferToken { token, expires_at }),
+        Ok(false) => HttpResponse::NotFound().finish(),
+        Err(err) => helpers::persist_failed(err),
+    }
+}
+
+#[actix_web::delete("/domain/{key}/transfer")]
+pub(crate) async fn cancel_transfer(path: web::Path<String>, app: Data<AppState>, req: HttpRequest) -> impl Responder {
+    let key = path.into_inner();
+
+    let mut domain = match helpers::find_owned(&key, app.as_ref()).await {
+        Ok(domain) => domain,
+        Err(error) => return error,
+    };
+    let before = domain.clone();
+
+    if domain.transfer.take().is_none() {
+        return HttpResponse::NotFound().finish();
+    }
+
+    domain.log("transfer_cancelled");
+    domain.touch();
+
+    match helpers::persist(app.as_ref(), &helpers::actor(&req, app.as_ref()), "cancel_transfer", Some(&before), Some(&domain)).await {
+        Ok(true) => HttpResponse::Ok().finish(),
+        Ok(false) => HttpResponse::NotFound().finish(),
+        Err(err) => helpers::persist_failed(err),
+    }
+}
+
+#[actix_web::post("/domain/{name}/{tld}/claim")]
+pub(crate) async fn claim_transfer(path: web::Path<(String, String)>, claim: web::Json<TransferClaim>, app: Data<AppState>, req: HttpRequest) -> impl Responder {
+    let (name, tld) = path.into_inner();
+    let name = registry::to_ascii(&name);
+
+    let mut domain = match app.db.find(&name, &tld).await {
+        Ok(Some(domain)) => domain,
+        Ok(None) => return HttpResponse::NotFound().finish(),
+        Err(_) => return HttpResponse::InternalServerError().finish(),
+    };
+    let before = domain.clone();
+
+    let now = chrono::Utc::now().timestamp();
+    let valid = match &domain.transfer {
+        Some(transfer) => transfer.expires_at > now && secret::verify(&claim.token, &transfer.token),
+        None => false,
+    };
+
+    if !valid {
+        return HttpResponse::Forbidden().json(Error {
+            msg: "Failed to claim domain",
+            error: "The transfer token is invalid or has expired.".into(),
+        });
+    }
+
+    let secret_key = secret::generate(31);
+    domain.set_key(&secret_key);
+    domain.transfer = None;
+    domain.recovery = None;
+    domain.recovery_requested = None;
+    domain.log("transferred");
+    domain.touch();
+
+    // the update only applies to the copy the token was checked against, so of two claims racing with the same token one
+    // is refused, as is a claim racing a cancel or a new transfer
+    let claimed = match helpers::persist(app.as_ref(), &helpers::actor(&req, app.as_ref()), "claim_transfer", Some(&before), Some(&domain)).await {
+        Err(err) if err.is::<Stale>() => Ok(false),
+        result => result,
+    };
+
+    match claimed {
+        Ok(true) => HttpResponse::Ok().json(domain.with_key(secret_key)),
+        Ok(false) => HttpResponse::Forbidden().json(Error {
+            msg: "Failed to claim domain",
+            error: "The transfer token is invalid or has expired.".into(),
+        }),
+        Err(err) => helpers::persist_failed(err),
+    }
+}
+
+#[actix_web::get("/domain/{key}/history")]
+pub(crate) async fn get_history(path: web::Path<String>, app: Data<AppState>) -> impl Responder {
+    match helpers::find_owned(&path.into_inner(), app.as_ref()).await {
+        Ok(domain) => HttpResponse::Ok().json(domain.history),
+        Err(error) => error,
+    }
+}
+
+#[actix_web::put("/domain/{key}/recovery")]
+pub(crate) async fn set_recovery(path: web::Path<String>, recovery: web::Json<Recovery>, app: Data<AppState>, req: HttpRequest) -> impl Responder {
+    let key = path.into_inner();
+    let recovery = recovery.into_inner();
+
+    if let Err(error) = helpers::validate_contact(recovery.contact.as_deref()) {
+        return error;
+    }
+
+    let mut domain = match helpers::find_owned(&key, app.as_ref()).await {
+        Ok(domain) => domain,
+        Err(error) => return error,
+    };
+    let before = domain.clone();
+
+    domain.recovery = recovery.contact.clone();
+    domain.touch();
+
+    match helpers::persist(app.as_ref(), &helpers::actor(&req, app.as_ref()), "set_recovery", Some(&before), Some(&domain)).await {
+        Ok(true) => HttpResponse::Ok().json(recovery),
+        Ok(false) => HttpResponse::NotFound().finish(),
+        Err(err) => helpers::persist_failed(err),
+    }
+}
+
+#[actix_web::post("/domain/{name}/{tld}/recovery")]
+pub(crate) async fn request_recovery(path: web::Path<(String, String)>, recovery: web::Json<Recovery>, app: Data<AppState>, req: HttpRequest) -> impl Responder {
+    let (name, tld) = path.into_inner();
+    let name = registry::to_ascii(&name);
+
+    let mut domain = match app.db.find(&name, &tld).await {
+        Ok(Some(domain)) => domain,
+        Ok(None) => return HttpResponse::NotFound().finish(),
+        Err(_) => return HttpResponse::InternalServerError().finish(),
+    };
+
+    let matches = match (&domain.recovery, &recovery.contact) {
+        (Some(stored), Some(given)) => stored.eq_ignore_ascii_case(given.trim()),
+        _ => false,
+    };
+
+    if matches && domain.recovery_requested.is_none() {
+        let before = domain.clone();
+        domain.recovery_requested = Some(chrono::Utc::now().timestamp());
+        if let Err(err) = helpers::persist(app.as_ref(), &helpers::actor(&req, app.as_ref()), "request_recovery", Some(&before), Some(&domain)).await {
+            return helpers::persist_failed(err);
         }
+    }
+
+    HttpResponse::Accepted().finish()
+}
+
+#[actix_web::post("/registry/domain/{name}/{tld}/recover")]
+pub(crate) async fn approve_recovery(path: web::Path<(String, String)>, app: Data<AppState>, req: HttpRequest) -> impl Responder {
+    let key_name = match helpers::authorize(&req, app.as_ref(), Scope::DeleteAny) {
+        Ok(name) => name,
+        Err(error) => return error,
+    };
+
+    let (name, tld) = path.into_inner();
+    let name = registry::to_ascii(&name);
+
+    let mut domain = match app.db.find(&name, &tld).await {
+        Ok(Some(domain)) => domain,
+        Ok(None) => return HttpResponse::NotFound().finish(),
+        Err(_) => return HttpResponse::InternalServerError().finish(),
+    };
+    let before = domain.clone();
+
+    if domain.recovery_requested.is_none() {
+        return HttpResponse::Conflict().json(Error {
+            msg: "Failed to recover domain",
+            error: "No recovery has been requested for this domain.".into(),
+        });
+    }
+
+    let secret_key = secret::generate(31);
+    domain.set_key(&secret_key);
+    domain.recovery_requested = None;
+    domain.transfer = None;
+    domain.log("recovered");
+    domain.touch();
+
+    match helpers::persist(app.as_ref(), &format!("key:{key_name}"), "approve_recovery", Some(&before), Some(&domain)).await {
+        Ok(true) => HttpResponse::Ok().json(RecoveredDomain {
+            domain: format!("{}.{}", domain.name, domain.tld),
+            contact: domain.recovery,
+            secret_key,
+        }),
+        Ok(false) => HttpResponse::NotFound().finish(),
+        Err(err) => helpers::persist_failed(err),
+    }
+}
+
+#[actix_web::delete("/domain/{key}")]
+pub(crate) async fn delete_domain(path: web::Path<String>, app: Data<AppState>, req: HttpRequest) -> impl Responder {
+    let domain = match helpers::find_owned(&path.into_inner(), app.as_ref()).await {
+        Ok(domain) => domain,
+        Err(error) => return error,
+    };
+
+    match helpers::persist(app.as_ref(), &helpers::actor(&req, app.as_ref()), "delete", Some(&domain), None).await {
+        Ok(true) => HttpResponse::Ok().finish(),
+        Ok(false) => HttpResponse::NotFound().finish(),
+        Err(_) => HttpResponse::InternalServerError().finish(),
+    }
+}
+
+#[actix_web::delete("/registry/domain/{name}/{tld}")]
+pub(crate) async fn remove_domain(path: web::Path<(String, String)>, app: Data<AppState>, req: HttpRequest) -> impl Responder {
+    let key_name = match helpers::authorize(&req, app.as_ref(), Scope::DeleteAny) {
+        Ok(name) => name,
+        Err(error) => return error,
+    };
+
+    let (name, tld) = path.into_inner();
+    let name = registry::to_ascii(&name);
+
+    let domain = match app.db.find(&name, &tld).await {
+        Ok(Some(domain)) => domain,
+        Ok(None) => return HttpResponse::NotFound().finish(),
+        Err(_) => return HttpResponse::InternalServerError().finish(),
+    };
+
+    match helpers::persist(app.as_ref(), &format!("key:{key_name}"), "delete", Some(&domain), None).await {
+        Ok(true) => HttpResponse::Ok().finish(),
+        Ok(false) => HttpResponse::NotFound().finish(),
         Err(_) => HttpResponse::InternalServerError().finish(),
     }
 }
@@ -272,7 +963,7 @@ pub(crate) async fn check_domain(query: web::Json<DomainQuery>, app: Data<AppSta
 
     let _connector = builder.build();
 
-    let result = helpers::is_domain_taken(&name, tld.as_deref(), app).await;
+    let result = helpers::is_domain_taken(&registry::to_ascii(&name), tld.as_deref(), app).await;
     HttpResponse::Ok().json(result)
 }
 
@@ -315,10 +1006,14 @@ pub(crate) async fn get_domains(query: web::Query<PaginationParams>, app: Data<A
     let _public_key = secret_key.public_key(&secp);
 
     let skip = (page - 1) * limit;
-    let find_options = FindOptions::builder().skip(Some(skip as u64)).limit(Some(limit as i64)).build();
 
-    let cursor = match app.db.find(None, find_options).await {
-        Ok(res) => res,
+    let listed = match query.alive {
+        Some(alive) => helpers::list_by_health(app.as_ref(), alive, skip as u64, limit as u64).await,
+        None => app.db.list_public(skip as u64, limit as u64).await,
+    };
+
+    let domains: Vec<ResponseDomain> = match listed {
+        Ok(res) => res.into_iter().map(ResponseDomain::from).collect(),
         Err(err) => {
             return HttpResponse::InternalServerError().json(Error {
                 msg: "Failed to fetch cursor",
@@ -327,22 +1022,143 @@ pub(crate) async fn get_domains(query: web::Query<PaginationParams>, app: Data<A
         }
     };
 
-    let domains: Vec<ResponseDomain> = cursor
-        .filter_map(|result| async {
-            match result {
-                Ok(domain) => Some(ResponseDomain {
-                    tld: domain.tld,
-                    name: domain.name,
-                    ip: domain.ip,
-                }),
-                Err(_) => None,
-            }
-        })
-        .collect()
-        .await;
-
     HttpResponse::Ok().json(PaginationResponse { domains, page, limit })
 }
 
+#[actix_web::get("/dns-query")]
+pub(crate) async fn dns_query_get(query: web::Query<DnsQuery>, app: Data<AppState>) -> impl Responder {
+    match URL_SAFE_NO_PAD.decode(query.dns.trim_end_matches('=')) {
+        Ok(packet) => helpers::dns_message(&packet, &app.resolver).await,
+        Err(err) => HttpResponse::BadRequest().json(Error {
+            msg: "Invalid dns parameter",
+            error: err.to_string(),
+        }),
+    }
+}
+
+#[actix_web::post("/dns-query")]
+pub(crate) async fn dns_query_post(body: web::Bytes, req: HttpRequest, app: Data<AppState>) -> impl Responder {
+    let content_type = req.headers().get(header::CONTENT_TYPE).and_then(|value| value.to_str().ok()).unwrap_or("");
+
+    if content_type != "application/dns-message" {
+        return HttpResponse::UnsupportedMediaType().json(Error {
+            msg: "Invalid content type",
+            error: "Expected application/dns-message".into(),
+        });
+    }
+
+    helpers::dns_message(&body, &app.resolver).await
+}
+
 #[actix_web::get("/tlds")]
-pub(crate) async fn get_tlds(app: Data<AppState>) -> impl Responder { HttpResponse::Ok().json(&*app.config.tld_list()) }
+pub(crate) async fn get_tlds(app: Data<AppState>) -> impl Responder {
+    match app.tlds.tlds().await {
+        Ok(tlds) => HttpResponse::Ok().json(tlds),
+        Err(err) => HttpResponse::InternalServerError().json(Error {
+            msg: "Failed to fetch TLDs",
+            error: err.to_string(),
+        }),
+    }
+}
+
+#[actix_web::post("/registry/tlds")]
+pub(crate) async fn add_tld(tld: web::Json<Tld>, app: Data<AppState>, req: HttpRequest) -> impl Responder {
+    let key_name = match helpers::authorize(&req, app.as_ref(), Scope::ManageTlds) {
+        Ok(name) => name,
+        Err(error) => return error,
+    };
+
+    let mut tld = tld.into_inner();
+    tld.name = tld.name.to_lowercase();
+
+    if let Err(error) = tld.validate() {
+        return HttpResponse::BadRequest().json(Error { msg: "Invalid TLD", error });
+    }
+
+    match app.tlds.find_tld(&tld.name).await {
+        Ok(None) => {}
+        Ok(Some(_)) => {
+            return HttpResponse::Conflict().json(Error {
+                msg: "Failed to add TLD",
+                error: format!("TLD '{}' already exists.", tld.name),
+            })
+        }
+        Err(_) => return HttpResponse::InternalServerError().finish(),
+    }
+
+    if audit::record(app.audit.as_ref(), AuditEntry::tld(&format!("key:{key_name}"), "add_tld", None, Some(&tld))).await.is_err() {
+        return HttpResponse::InternalServerError().finish();
+    }
+
+    match app.tlds.save_tld(&tld).await {
+        Ok(_) => HttpResponse::Ok().json(tld),
+        Err(_) => HttpResponse::InternalServerError().finish(),
+    }
+}
+
+#[actix_web::put("/registry/tlds/{name}")]
+pub(crate) async fn update_tld(path: web::Path<String>, update: web::Json<TldUpdate>, app: Data<AppState>, req: HttpRequest) -> impl Responder {
+    let key_name = match helpers::authorize(&req, app.as_ref(), Scope::ManageTlds) {
+        Ok(name) => name,
+        Err(error) => return error,
+    };
+
+    let mut tld = match app.tlds.find_tld(&path.into_inner().to_lowercase()).await {
+        Ok(Some(tld)) => tld,
+        Ok(None) => return HttpResponse::NotFound().finish(),
+        Err(_) => return HttpResponse::InternalServerError().finish(),
+    };
+    let before = tld.clone();
+
+    tld.apply(update.into_inner());
+
+    if let Err(error) = tld.validate() {
+        return HttpResponse::BadRequest().json(Error { msg: "Invalid TLD", error });
+    }
+
+    if audit::record(app.audit.as_ref(), AuditEntry::tld(&format!("key:{key_name}"), "update_tld", Some(&before), Some(&tld))).await.is_err() {
+        return HttpResponse::InternalServerError().finish();
+    }
+
+    match app.tlds.save_tld(&tld).await {
+        Ok(_) => HttpResponse::Ok().json(tld),
+        Err(_) => HttpResponse::InternalServerError().finish(),
+    }
+}
+
+#[actix_web::delete("/registry/tlds/{name}")]
+pub(crate) async fn delete_tld(path: web::Path<String>, app: Data<AppState>, req: HttpRequest) -> impl Responder {
+    let key_name = match helpers::authorize(&req, app.as_ref(), Scope::ManageTlds) {
+        Ok(name) => name,
+        Err(error) => return error,
+    };
+
+    let name = path.into_inner().to_lowercase();
+
+    let tld = match app.tlds.find_tld(&name).await {
+        Ok(Some(tld)) => tld,
+        Ok(None) => return HttpResponse::NotFound().finish(),
+        Err(_) => return HttpResponse::InternalServerError().finish(),
+    };
+
+    match app.db.search("", Some(&name), 0, 1).await {
+        Ok(domains) if domains.is_empty() => {}
+        Ok(_) => {
+            return HttpResponse::Conflict().json(Error {
+                msg: "Failed to delete TLD",
+                error: format!("There are still domains registered under .{name}."),
+            })
+        }
+        Err(_) => return HttpResponse::InternalServerError().finish(),
+    }
+
+    if audit::record(app.audit.as_ref(), AuditEntry::tld(&format!("key:{key_name}"), "delete_tld", Some(&tld), None)).await.is_err() {
+        return HttpResponse::InternalServerError().finish();
+    }
+
+    match app.tlds.delete_tld(&name).await {
+        Ok(true) => HttpResponse::Ok().finish(),
+        Ok(false) => HttpResponse::NotFound().finish(),
+        Err(_) => HttpResponse::InternalServerError().finish(),
+    }
+}
@@ -0,0 +1,343 @@
+use crate::secret;
+use anyhow::{anyhow, Error};
+use chrono::{DateTime, Datelike, Duration, NaiveDate, Utc};
+use serde::{Deserialize, Serialize};
+use std::{fmt, net::IpAddr, str::FromStr};
+
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum Scope {
+    Create,
+    DeleteAny,
+    ManageTlds,
+    ReadAudit,
+    ReadZones,
+    ClaimReserved,
+}
+
+impl Scope {
+    pub const ALL: [Scope; 6] = [Scope::Create, Scope::DeleteAny, Scope::ManageTlds, Scope::ReadAudit, Scope::ReadZones, Scope::ClaimReserved];
+}
+
+impl fmt::Display for Scope {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Scope::Create => "create",
+            Scope::DeleteAny => "delete-any",
+            Scope::ManageTlds => "manage-tlds",
+            Scope::ReadAudit => "read-audit",
+            Scope::ReadZones => "read-zones",
+            Scope::ClaimReserved => "claim-reserved",
+        })
+    }
+}
+
+impl FromStr for Scope {
+    type Err = Error;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        Scope::ALL.into_iter().find(|scope| scope.to_string() == value).ok_or_else(|| anyhow!("Unknown scope '{value}'"))
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct ApiKey {
+    pub hash: String,
+    pub scopes: Vec<Scope>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expires_at: Option<i64>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub allowed_ips: Vec<IpAddr>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub created_at: Option<i64>,
+    #[serde(default, skip_serializing_if = "Quota::is_unlimited")]
+    pub quota: Quota,
+}
+
+#[derive(Clone, Copy, Debug, Default, Deserialize, Serialize, PartialEq, Eq, clap::Args)]
+pub struct Quota {
+    /// Requests allowed per day
+    #[arg(long)]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub daily_requests: Option<u64>,
+    /// Requests allowed per month
+    #[arg(long)]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub monthly_requests: Option<u64>,
+    /// Domains that may be created per day
+    #[arg(long)]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub daily_domains: Option<u64>,
+    /// Domains that may be created per month
+    #[arg(long)]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub monthly_domains: Option<u64>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Counter {
+    Request,
+    Domain,
+}
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct Usage {
+    pub day: String,
+    pub month: String,
+    pub requests_today: u64,
+    pub requests_this_month: u64,
+    pub requests_total: u64,
+    pub domains_today: u64,
+    pub domains_this_month: u64,
+    pub domains_total: u64,
+    pub last_used: Option<i64>,
+}
+
+impl Quota {
+    pub fn is_unlimited(&self) -> bool { *self == Quota::default() }
+
+    pub fn or(self, fallback: Quota) -> Quota {
+        Quota {
+            daily_requests: self.daily_requests.or(fallback.daily_requests),
+            monthly_requests: self.monthly_requests.or(fallback.monthly_requests),
+            daily_domains: self.daily_domains.or(fallback.daily_domains),
+            monthly_domains: self.monthly_domains.or(fallback.monthly_domains),
+        }
+    }
+}
+
+impl Usage {
+    pub fn roll(&mut self, now: DateTime<Utc>) {
+        let (day, month) = (now.format("%Y-%m-%d").to_string(), now.format("%Y-%m").to_string());
+
+        if self.month != month {
+            self.month = month;
+            self.requests_this_month = 0;
+            self.domains_this_month = 0;
+        }
+
+        if self.day != day {
+            self.day = day;
+            self.requests_today = 0;
+            self.domains_today = 0;
+        }
+    }
+
+    pub fn count(&mut self, counter: Counter, now: DateTime<Utc>) {
+        match counter {
+            Counter::Request => {
+                self.requests_today += 1;
+                self.requests_this_month += 1;
+                self.requests_total += 1;
+            }
+            Counter::Domain => {
+                self.domains_today += 1;
+                self.domains_this_month += 1;
+                self.domains_total += 1;
+            }
+        }
+        self.last_used = Some(now.timestamp());
+    }
+
+    /// Takes back a `count`, for a reservation whose action failed.
+    pub fn uncount(&mut self, counter: Counter) {
+        let counters = match counter {
+            Counter::Request => [&mut self.requests_today, &mut self.requests_this_month, &mut self.requests_total],
+            Counter::Domain => [&mut self.domains_today, &mut self.domains_this_month, &mut self.domains_total],
+        };
+        counters.into_iter().for_each(|count| *count = count.saturating_sub(1));
+    }
+
+    /// Returns the unix time at which the exhausted quota resets.
+    pub fn exceeded(&self, counter: Counter, quota: &Quota, now: DateTime<Utc>) -> Option<i64> {
+        let (today, month, daily, monthly) = match counter {
+            Counter::Request => (self.requests_today, self.requests_this_month, quota.daily_requests, quota.monthly_requests),
+            Counter::Domain => (self.domains_today, self.domains_this_month, quota.daily_domains, quota.monthly_domains),
+        };
+
+        if monthly.is_some_and(|limit| month >= limit) {
+            return Some(next_month(now));
+        }
+
+        daily.filter(|limit| today >= *limit).map(|_| next_day(now))
+    }
+}
+
+fn next_day(now: DateTime<Utc>) -> i64 { (now.date_naive() + Duration::days(1)).and_hms_opt(0, 0, 0).map_or(now.timestamp() + 86400, |at| at.and_utc().timestamp()) }
+
+fn next_month(now: DateTime<Utc>) -> i64 {
+    let (year, month) = if now.month() == 12 { (now.year() + 1, 1) } else { (now.year(), now.month() + 1) };
+    NaiveDate::from_ymd_opt(year, month, 1).and_then(|date| date.and_hms_opt(0, 0, 0)).map_or(next_day(now), |at| at.and_utc().timestamp())
+}
+
+impl ApiKey {
+    pub fn new(key: &str, scopes: Vec<Scope>, expires_at: Option<i64>, allowed_ips: Vec<IpAddr>, quota: Quota) -> Self {
+        ApiKey {
+            hash: secret::hash(key),
+            scopes,
+            expires_at,
+            allowed_ips,
+            created_at: Some(chrono::Utc::now().timestamp()),
+            quota,
+        }
+    }
+
+    // keys created before scopes existed hold only the hash and keep full access
+    pub fn parse(value: &str) -> Result<Self, Error> {
+        if value.starts_with('{') {
+            return Ok(serde_json::from_str(value)?);
+        }
+
+        Ok(ApiKey {
+            hash: value.to_string(),
+            scopes: Scope::ALL.to_vec(),
+            expires_at: None,
+            allowed_ips: vec![],
+            created_at: None,
+            quota: Quota::default(),
+        })
+    }
+
+    pub fn to_value(&self) -> Result<String, Error> { Ok(serde_json::to_string(self)?) }
+
+    pub fn is_expired(&self, now: i64) -> bool { self.expires_at.is_some_and(|expires_at| expires_at <= now) }
+
+    pub fn allows(&self, ip: Option<IpAddr>) -> bool { self.allowed_ips.is_empty() || ip.is_some_and(|ip| self.allowed_ips.contains(&ip)) }
+
+    pub fn has(&self, scope: Scope) -> bool { self.scopes.contains(&scope) }
+
+    pub fn scope_list(&self) -> String { self.scopes.iter().map(Scope::to_string).collect::<Vec<_>>().join(", ") }
+
+    pub fn ip_list(&self) -> String {
+        match self.allowed_ips.is_empty() {
+            true => "any".into(),
+            false => self.allowed_ips.iter().map(IpAddr::to_string).collect::<Vec<_>>().join(", "),
+        }
+    }
+
+    pub fn expiry(&self) -> String {
+        self.expires_at
+            .and_then(|at| chrono::DateTime::from_timestamp(at, 0))
+            .map(|at| at.format("%Y-%m-%d %H:%M UTC").to_string())
+            .unwrap_or_else(|| "never".into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn scopes_round_trip_through_their_names() {
+        for scope in Scope::ALL {
+            assert_eq!(scope.to_string().parse::<Scope>().unwrap(), scope);
+        }
+
+        assert!("admin".parse::<Scope>().is_err());
+        assert!("Create".parse::<Scope>().is_err());
+    }
+
+    #[test]
+    fn key_only_has_its_scopes() {
+        let key = ApiKey::new("secret", vec![Scope::Create, Scope::ReadZones], None, vec![], Quota::default());
+
+        assert!(key.has(Scope::Create) && key.has(Scope::ReadZones));
+        assert!(!key.has(Scope::DeleteAny));
+        assert!(!key.has(Scope::ClaimReserved));
+        assert!(secret::verify("secret", &key.hash));
+    }
+
+    #[test]
+    fn parse_reads_stored_keys() {
+        let key = ApiKey::new("secret", vec![Scope::ReadAudit], Some(100), vec!["203.0.113.7".parse().unwrap()], Quota::default());
+        let parsed = ApiKey::parse(&key.to_value().unwrap()).unwrap();
+
+        assert_eq!(parsed.scopes, vec![Scope::ReadAudit]);
+        assert_eq!(parsed.expires_at, Some(100));
+        assert!(ApiKey::parse("{not json").is_err());
+    }
+
+    #[test]
+    fn legacy_keys_keep_full_access() {
+        let key = ApiKey::parse(&secret::hash("secret")).unwrap();
+
+        assert!(Scope::ALL.into_iter().all(|scope| key.has(scope)));
+        assert!(!key.is_expired(i64::MAX));
+        assert!(key.allows(None));
+    }
+
+    #[test]
+    fn expiry_and_allowed_ips() {
+        let ip: IpAddr = "203.0.113.7".parse().unwrap();
+        let key = ApiKey::new("secret", vec![], Some(100), vec![ip], Quota::default());
+
+        assert!(!key.is_expired(99));
+        assert!(key.is_expired(100));
+        assert!(key.allows(Some(ip)));
+        assert!(!key.allows(Some("203.0.113.8".parse().unwrap())));
+        assert!(!key.allows(None));
+    }
+
+    fn at(value: &str) -> DateTime<Utc> { value.parse().unwrap() }
+
+    fn domain_quota(daily: u64, monthly: u64) -> Quota {
+        Quota {
+            daily_domains: Some(daily),
+            monthly_domains: Some(monthly),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn usage_rolls_over_by_day_and_month() {
+        let mut usage = Usage::default();
+        usage.roll(at("2025-01-31T23:00:00Z"));
+        usage.count(Counter::Domain, at("2025-01-31T23:00:00Z"));
+
+        usage.roll(at("2025-01-31T23:59:59Z"));
+        assert_eq!((usage.domains_today, usage.domains_this_month), (1, 1));
+
+        usage.roll(at("2025-02-01T00:00:00Z"));
+        assert_eq!((usage.domains_today, usage.domains_this_month, usage.domains_total), (0, 0, 1));
+    }
+
+    #[test]
+    fn quota_resets_at_the_next_day_or_month() {
+        let now = at("2025-12-31T12:00:00Z");
+        let mut usage = Usage::default();
+        usage.roll(now);
+
+        assert_eq!(usage.exceeded(Counter::Domain, &domain_quota(1, 5), now), None);
+        usage.count(Counter::Domain, now);
+        assert_eq!(usage.exceeded(Counter::Domain, &domain_quota(1, 5), now), Some(at("2026-01-01T00:00:00Z").timestamp()));
+        assert_eq!(usage.exceeded(Counter::Domain, &domain_quota(5, 1), now), Some(at("2026-01-01T00:00:00Z").timestamp()));
+        assert_eq!(usage.exceeded(Counter::Request, &domain_quota(1, 1), now), None);
+
+        let now = at("2025-06-15T12:00:00Z");
+        usage.roll(now);
+        usage.count(Counter::Domain, now);
+        assert_eq!(usage.exceeded(Counter::Domain, &domain_quota(1, 5), now), Some(at("2025-06-16T00:00:00Z").timestamp()));
+        assert_eq!(usage.exceeded(Counter::Domain, &domain_quota(5, 1), now), Some(at("2025-07-01T00:00:00Z").timestamp()));
+    }
+
+    #[test]
+    fn uncount_gives_back_a_reservation() {
+        let now = at("2025-06-15T12:00:00Z");
+        let mut usage = Usage::default();
+        usage.roll(now);
+        usage.count(Counter::Domain, now);
+        usage.uncount(Counter::Domain);
+
+        assert_eq!((usage.domains_today, usage.domains_this_month, usage.domains_total), (0, 0, 0));
+        usage.uncount(Counter::Domain);
+        assert_eq!(usage.domains_total, 0);
+    }
+
+    #[test]
+    fn key_quota_falls_back_to_the_registry_default() {
+        let quota = Quota { daily_domains: Some(2), ..Default::default() }.or(domain_quota(10, 20));
+
+        assert_eq!((quota.daily_domains, quota.monthly_domains), (Some(2), Some(20)));
+        assert!(Quota::default().is_unlimited());
+    }
+}
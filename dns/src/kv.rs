@@ -1,6 +1,7 @@
+use crate::keys::{ApiKey, Counter, Quota, Usage};
 use anyhow::{anyhow, Error};
 use prettytable::{format, row, Table};
-use std::{collections::HashMap, fs::File, str::from_utf8};
+use std::{collections::HashMap, fs::File, str::from_utf8, time::{Duration, Instant}};
 use ldap3::LdapConn;
 use ldap3::Mod;
 use std::collections::HashSet;
@@ -11,9 +12,25 @@ use sxd_document::parser;
 use sxd_xpath::{Context, Factory};
 use std::net::UdpSocket;
 #[cfg(unix)] use rustix::fs::{chmod, chown, Mode, Uid, Gid};
+
+// how long to wait for the server or another command to let go of the key database
+const LOCK_WAIT: Duration = Duration::from_secs(5);
+
+// sled locks the database for as long as it is open, so every user opens it briefly and retries while it is taken
+fn open(path: &str) -> Result<sled::Db, Error> {
+    let deadline = Instant::now() + LOCK_WAIT;
+
+    loop {
+        match sled::open(path) {
+            Err(sled::Error::Io(_)) if Instant::now() < deadline => std::thread::sleep(Duration::from_millis(10)),
+            result => return Ok(result?),
+        }
+    }
+}
+
 pub fn get(path: &String, key: &String) -> Result<String, Error> {
     log::debug!("{}", path);
-    let db = sled::open(&path)?;
+    let db = open(path)?;
     let value = db.get(&key)?;
 
     match value {
@@ -26,7 +43,7 @@ pub fn get(path: &String, key: &String) -> Result<String, Error> {
 }
 
 pub fn set(path: &String, key: &String, value: &String) -> Result<(), Error> {
-    let db = sled::open(&path)?;
+    let db = open(path)?;
     db.insert(&key, sled::IVec::from(macros_rs::fmt::str!(value.clone())))?;
     db.flush()?;
 
@@ -60,8 +77,9 @@ pub fn set(path: &String, key: &String, value: &String) -> Result<(), Error> {
 }
 
 pub fn remove(path: &String, key: &String) -> Result<(), Error> {
-    let db = sled::open(&path)?;
+    let db = open(path)?;
     db.remove(&key)?;
+    db.open_tree("usage")?.remove(&key)?;
     db.flush()?;
 
     let mut user_input = String::new();
@@ -89,11 +107,11 @@ pub fn remove(path: &String, key: &String) -> Result<(), Error> {
 }
 
 pub fn list(path: &String, silent: bool) -> Result<(), Error> {
-    let db = sled::open(&path)?;
+    let db = open(path)?;
     let mut table = Table::new();
     let mut store: HashMap<String, String> = HashMap::new();
 
-    table.set_titles(row!["Key", "Value"]);
+    table.set_titles(row!["Name", "Scopes", "Expires", "Allowed IPs"]);
     table.set_format(*format::consts::FORMAT_NO_BORDER_LINE_SEPARATOR);
 
     if silent {
@@ -105,14 +123,112 @@ pub fn list(path: &String, silent: bool) -> Result<(), Error> {
     } else {
         for row in db.iter() {
             let (key, val) = row.expect("Could not read row");
-            table.add_row(row![String::from_utf8(key.to_vec())?, String::from_utf8(val.to_vec())?]);
+            let api_key = ApiKey::parse(from_utf8(&val)?)?;
+            table.add_row(row![String::from_utf8(key.to_vec())?, api_key.scope_list(), api_key.expiry(), api_key.ip_list()]);
         }
         Ok(table.printstd())
     }
 }
 
+pub fn usage(path: &str, name: &str) -> Result<Usage, Error> { Keys::open(path)?.usage(name) }
+
+/// The key database as used by the server. It is only held open for the length of each call.
+#[derive(Clone)]
+pub struct Keys {
+    path: String,
+}
+
+impl Keys {
+    pub fn open(path: &str) -> Result<Self, Error> {
+        drop(open(path)?);
+        Ok(Keys { path: path.to_string() })
+    }
+
+    pub fn get(&self, name: &str) -> Result<ApiKey, Error> {
+        match open(&self.path)?.get(name)? {
+            Some(value) => ApiKey::parse(from_utf8(&value)?),
+            None => Err(anyhow!("Key does not exist")),
+        }
+    }
+
+    pub fn usage(&self, name: &str) -> Result<Usage, Error> {
+        let mut usage: Usage = match open(&self.path)?.open_tree("usage")?.get(name)? {
+            Some(value) => serde_json::from_slice(&value)?,
+            None => Usage::default(),
+        };
+
+        usage.roll(chrono::Utc::now());
+        Ok(usage)
+    }
+
+    /// Counts one use of `counter` for the key, unless `quota` is exhausted, in which case the reset time is returned.
+    /// The check and the count are a single swap, so concurrent requests cannot both take the last use.
+    pub fn track(&self, name: &str, counter: Counter, quota: Option<&Quota>) -> Result<Option<i64>, Error> {
+        let now = chrono::Utc::now();
+
+        self.update(name, |usage| {
+            usage.roll(now);
+            if let Some(after) = quota.and_then(|quota| usage.exceeded(counter, quota, now)) {
+                return Some(after);
+            }
+            usage.count(counter, now);
+            None
+        })
+    }
+
+    /// Gives back a use taken by `track` when the action it was reserved for failed.
+    pub fn untrack(&self, name: &str, counter: Counter) -> Result<(), Error> {
+        self.update(name, |usage| {
+            usage.uncount(counter);
+            None
+        })
+        .map(drop)
+    }
+
+    // retries `apply` until its result is swapped in; nothing is written when it returns `Some`
+    fn update(&self, name: &str, apply: impl Fn(&mut Usage) -> Option<i64>) -> Result<Option<i64>, Error> {
+        let db = open(&self.path)?;
+        let tree = db.open_tree("usage")?;
+
+        loop {
+            let current = tree.get(name)?;
+            let mut usage: Usage = match &current {
+                Some(value) => serde_json::from_slice(value)?,
+                None => Usage::default(),
+            };
+
+            if let Some(after) = apply(&mut usage) {
+                return Ok(Some(after));
+            }
+
+            if tree.compare_and_swap(name, current, Some(serde_json::to_vec(&usage)?))?.is_ok() {
+                tree.flush()?;
+                return Ok(None);
+            }
+        }
+    }
+}
+
+pub fn migrate(path: &str) -> Result<usize, Error> {
+    let db = open(path)?;
+    let mut count = 0;
+
+    for row in db.iter() {
+        let (key, val) = row?;
+        let value = from_utf8(&val)?;
+
+        if !value.starts_with('{') && !crate::secret::is_hashed(value) {
+            db.insert(key, crate::secret::hash(value).as_bytes())?;
+            count += 1;
+        }
+    }
+
+    db.flush()?;
+    Ok(count)
+}
+
 pub fn save(path: &String, filename: &String) -> Result<(), Error> {
-    let db = sled::open(path)?;
+    let db = open(path)?;
     let out = File::create(filename)?;
     let mut table = Table::new();
 
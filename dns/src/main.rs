@@ -1,8 +1,24 @@
+mod audit;
+mod backup;
+mod changes;
 mod cli;
 mod config;
+mod expiry;
+mod filter;
+mod health;
 mod http;
+mod keys;
 mod kv;
+mod registry;
+mod replication;
+mod resolver;
 mod secret;
+mod signing;
+mod store;
+mod target;
+mod verification;
+mod webhook;
+mod zone;
 
 use clap::{Parser, Subcommand};
 use clap_verbosity_flag::{LogLevel, Verbosity};
@@ -34,6 +50,278 @@ enum Commands {
         #[command(subcommand)]
         command: Key,
     },
+    /// Hash plaintext domain and API keys (run once after upgrading)
+    Migrate,
+    /// Manage registered domains
+    Domain {
+        #[command(subcommand)]
+        command: Domain,
+    },
+    /// Manage top-level domains and their registration policy
+    Tld {
+        #[command(subcommand)]
+        command: Tld,
+    },
+    /// Inspect the offensive-name filter
+    Filter {
+        #[command(subcommand)]
+        command: Filter,
+    },
+    /// Export DNS zones
+    Zone {
+        #[command(subcommand)]
+        command: Zone,
+    },
+    /// Inspect the registry audit log
+    Audit {
+        #[command(subcommand)]
+        command: Audit,
+    },
+    /// Inspect webhook deliveries
+    Webhook {
+        #[command(subcommand)]
+        command: Webhook,
+    },
+    /// Replicate domains from peer registries
+    Peer {
+        #[command(subcommand)]
+        command: Peer,
+    },
+    /// Check that domain targets are reachable
+    Health {
+        #[command(subcommand)]
+        command: Health,
+    },
+    /// Manage the key used to sign domain responses
+    Signing {
+        #[command(subcommand)]
+        command: Signing,
+    },
+}
+
+#[derive(Subcommand)]
+enum Domain {
+    /// List domains
+    #[command(visible_alias = "ls")]
+    List {
+        /// Only list domains under this TLD
+        #[arg(short, long)]
+        tld: Option<String>,
+        /// Page number
+        #[arg(short, long, default_value_t = 1)]
+        page: u64,
+        /// Domains per page
+        #[arg(short, long, default_value_t = 50)]
+        limit: u64,
+        /// Print JSON instead of a table
+        #[arg(long)]
+        json: bool,
+    },
+    /// Show a domain
+    #[command(visible_alias = "i")]
+    Info {
+        /// Domain (name.tld)
+        domain: String,
+        /// Print JSON instead of a table
+        #[arg(long)]
+        json: bool,
+    },
+    /// Delete a domain
+    #[command(visible_alias = "rm")]
+    Remove {
+        /// Domain (name.tld)
+        domain: String,
+    },
+    /// Point a domain at a new IP address or URL
+    SetIp {
+        /// Domain (name.tld)
+        domain: String,
+        /// New IP address or URL
+        ip: String,
+    },
+    /// Suspend a domain, it stops resolving and cannot be changed by its owner
+    Ban {
+        /// Domain (name.tld)
+        domain: String,
+        /// Reason shown in domain info
+        #[arg(short, long)]
+        reason: Option<String>,
+    },
+    /// Lift a domain suspension
+    Unban {
+        /// Domain (name.tld)
+        domain: String,
+    },
+    /// Export all domains for backup or migration
+    Export {
+        /// Output file name
+        filename: String,
+        /// File format, detected from the file extension by default
+        #[arg(short, long)]
+        format: Option<backup::Format>,
+        /// Leave out secret key hashes and pending transfers
+        #[arg(short, long)]
+        redact: bool,
+    },
+    /// Import domains from an export, validating each entry (dry run unless --apply)
+    Import {
+        /// Input file name
+        filename: String,
+        /// File format, detected from the file extension by default
+        #[arg(short, long)]
+        format: Option<backup::Format>,
+        /// Write the valid domains to the store
+        #[arg(short, long)]
+        apply: bool,
+        /// Accept names on the reserved lists
+        #[arg(long)]
+        claim_reserved: bool,
+    },
+    /// Search domains by name or target
+    Search {
+        /// Text to look for
+        query: String,
+        /// Only search under this TLD
+        #[arg(short, long)]
+        tld: Option<String>,
+        /// Maximum number of results
+        #[arg(short, long, default_value_t = 50)]
+        limit: u64,
+        /// Print JSON instead of a table
+        #[arg(long)]
+        json: bool,
+    },
+}
+
+#[derive(Subcommand)]
+enum Tld {
+    /// List TLDs with their policy
+    #[command(visible_alias = "ls")]
+    List {
+        /// Print JSON instead of a table
+        #[arg(long)]
+        json: bool,
+    },
+    /// Add a TLD
+    #[command(visible_alias = "mk")]
+    Add {
+        /// TLD name
+        name: String,
+        #[command(flatten)]
+        policy: registry::TldUpdate,
+    },
+    /// Change the policy of a TLD
+    Set {
+        /// TLD name
+        name: String,
+        #[command(flatten)]
+        policy: registry::TldUpdate,
+    },
+    /// Remove a TLD without any domains
+    #[command(visible_alias = "rm")]
+    Remove {
+        /// TLD name
+        name: String,
+    },
+}
+
+#[derive(Subcommand)]
+enum Filter {
+    /// Check a name against the filter and explain the result
+    Test {
+        /// Name to check
+        name: String,
+    },
+}
+
+#[derive(Subcommand)]
+enum Zone {
+    /// Write an RFC 1035 master file for a TLD
+    Export {
+        /// TLD to export
+        #[arg(short, long)]
+        tld: String,
+        /// Output file name, prints to stdout when omitted
+        #[arg(short, long)]
+        output: Option<String>,
+        /// SOA serial, defaults to the latest change sequence number
+        #[arg(short, long)]
+        serial: Option<u32>,
+    },
+}
+
+#[derive(Subcommand)]
+enum Audit {
+    /// List recent audit entries
+    #[command(visible_alias = "ls")]
+    List {
+        /// Only show entries for this domain (name.tld)
+        #[arg(short, long)]
+        domain: Option<String>,
+        /// Maximum number of entries
+        #[arg(short, long, default_value_t = 50)]
+        limit: u64,
+    },
+}
+
+#[derive(Subcommand)]
+enum Webhook {
+    /// List recent deliveries
+    #[command(visible_alias = "ls")]
+    List {
+        /// Only show deliveries with this status
+        #[arg(short, long)]
+        status: Option<webhook::Status>,
+        /// Maximum number of deliveries
+        #[arg(short, long, default_value_t = 50)]
+        limit: u64,
+    },
+    /// Queue a delivery again with a fresh set of attempts
+    Retry {
+        /// Delivery id
+        id: String,
+    },
+}
+
+#[derive(Subcommand)]
+enum Peer {
+    /// Show the sync state of every configured peer
+    #[command(visible_alias = "ls")]
+    List,
+    /// Pull changes from peers now
+    Sync {
+        /// Only sync this peer
+        name: Option<String>,
+    },
+    /// Copy a peer's domains again from scratch on the next sync
+    Reset {
+        /// Peer name
+        name: String,
+    },
+}
+
+#[derive(Subcommand)]
+enum Health {
+    /// List the last health check of each domain
+    #[command(visible_alias = "ls")]
+    List {
+        /// Only show domains whose target is unreachable
+        #[arg(short, long)]
+        dead: bool,
+        /// Maximum number of domains
+        #[arg(short, long, default_value_t = 50)]
+        limit: u64,
+    },
+    /// Probe every domain now
+    Check,
+}
+
+#[derive(Subcommand)]
+enum Signing {
+    /// Create the configured signing key
+    Generate,
+    /// Print the public key for pinning in browsers
+    Public,
 }
 
 #[derive(Subcommand)]
@@ -58,6 +346,17 @@ enum Key {
     Create {
         /// Key name
         name: String,
+        /// Allowed scope (create, delete-any, manage-tlds, read-audit, read-zones, claim-reserved), repeatable; defaults to create
+        #[arg(short, long = "scope")]
+        scopes: Vec<keys::Scope>,
+        /// Days until the key expires
+        #[arg(short, long)]
+        expires: Option<i64>,
+        /// Only accept the key from this IP, repeatable
+        #[arg(short, long = "allow-ip")]
+        allow_ips: Vec<std::net::IpAddr>,
+        #[command(flatten)]
+        quota: keys::Quota,
     },
     /// Remove API key
     #[command(visible_alias = "rm")]
@@ -89,9 +388,53 @@ fn main() {
         Commands::Key { command } => match command {
             Key::List => cli::list(&cli),
             Key::Info { name } => cli::info(&cli, name),
-            Key::Create { name } => cli::create(&cli, name),
+            Key::Create { name, scopes, expires, allow_ips, quota } => cli::create(&cli, name, scopes, *expires, allow_ips, *quota),
             Key::Delete { name } => cli::remove(&cli, name),
             Key::Export { filename } => cli::export(&cli, filename),
         },
+        Commands::Migrate => cli::migrate(&cli),
+        Commands::Domain { command } => match command {
+            Domain::List { tld, page, limit, json } => cli::domain_list(&cli, tld, *page, *limit, *json),
+            Domain::Info { domain, json } => cli::domain_info(&cli, domain, *json),
+            Domain::Remove { domain } => cli::domain_remove(&cli, domain),
+            Domain::SetIp { domain, ip } => cli::domain_set_ip(&cli, domain, ip),
+            Domain::Ban { domain, reason } => cli::domain_ban(&cli, domain, reason),
+            Domain::Unban { domain } => cli::domain_unban(&cli, domain),
+            Domain::Search { query, tld, limit, json } => cli::domain_search(&cli, query, tld, *limit, *json),
+            Domain::Export { filename, format, redact } => cli::domain_export(&cli, filename, *format, *redact),
+            Domain::Import { filename, format, apply, claim_reserved } => cli::domain_import(&cli, filename, *format, *apply, *claim_reserved),
+        },
+        Commands::Tld { command } => match command {
+            Tld::List { json } => cli::tld_list(&cli, *json),
+            Tld::Add { name, policy } => cli::tld_add(&cli, name, policy),
+            Tld::Set { name, policy } => cli::tld_set(&cli, name, policy),
+            Tld::Remove { name } => cli::tld_remove(&cli, name),
+        },
+        Commands::Filter { command } => match command {
+            Filter::Test { name } => cli::filter_test(&cli, name),
+        },
+        Commands::Zone { command } => match command {
+            Zone::Export { tld, output, serial } => cli::zone_export(&cli, tld, output, *serial),
+        },
+        Commands::Audit { command } => match command {
+            Audit::List { domain, limit } => cli::audit_list(&cli, domain, *limit),
+        },
+        Commands::Webhook { command } => match command {
+            Webhook::List { status, limit } => cli::webhook_list(&cli, *status, *limit),
+            Webhook::Retry { id } => cli::webhook_retry(&cli, id),
+        },
+        Commands::Peer { command } => match command {
+            Peer::List => cli::peer_list(&cli),
+            Peer::Sync { name } => cli::peer_sync(&cli, name),
+            Peer::Reset { name } => cli::peer_reset(&cli, name),
+        },
+        Commands::Health { command } => match command {
+            Health::List { dead, limit } => cli::health_list(&cli, *dead, *limit),
+            Health::Check => cli::health_check(&cli),
+        },
+        Commands::Signing { command } => match command {
+            Signing::Generate => cli::signing_generate(&cli),
+            Signing::Public => cli::signing_public(&cli),
+        },
     };
 }
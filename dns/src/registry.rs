@@ -0,0 +1,342 @@
+use crate::{
+    config::Config,
+    filter::Checker,
+    http::{Domain, DomainRecord, RecordKind, Subdomain},
+};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::{
+    fmt,
+    net::{Ipv4Addr, Ipv6Addr},
+};
+use unicode_security::MixedScript;
+
+pub(crate) const MAX_RECORDS: usize = 16;
+pub(crate) const MAX_SUBDOMAINS: usize = 32;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) enum Invalid {
+    Tld,
+    Name(usize),
+    Reserved,
+    Offensive,
+    Confusable,
+    Target,
+    Record(String, RecordKind),
+    Ttl,
+    Records,
+    Subdomain,
+    OffensiveSubdomain,
+    Subdomains,
+}
+
+impl fmt::Display for Invalid {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Invalid::Tld => f.write_str("The given TLD does not exist."),
+            Invalid::Name(max_length) => write!(f, "Invalid name, non-existent TLD, or name too long ({max_length} chars)."),
+            Invalid::Reserved => f.write_str("The given domain name is reserved."),
+            Invalid::Offensive => f.write_str("The given domain name is offensive."),
+            Invalid::Confusable => f.write_str("The given domain name mixes characters from different scripts."),
+            Invalid::Target => f.write_str("The domain must point to an IP address or http(s) URL."),
+            Invalid::Record(value, kind) => write!(f, "'{value}' is not a valid {kind:?} value."),
+            Invalid::Ttl => f.write_str("TTL must be between 60 and 86400 seconds."),
+            Invalid::Records => write!(f, "A domain can hold at most {MAX_RECORDS} records."),
+            Invalid::Subdomain => f.write_str("Invalid name, labels may only contain letters, digits and '-' (max 3 labels)."),
+            Invalid::OffensiveSubdomain => f.write_str("The given subdomain name is offensive."),
+            Invalid::Subdomains => write!(f, "A domain can hold at most {MAX_SUBDOMAINS} subdomains."),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, PartialEq, Eq, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum Charset {
+    Letters,
+    Digits,
+    Hyphen,
+}
+
+impl Charset {
+    fn allows(&self, c: char) -> bool {
+        match self {
+            Charset::Letters => c.is_alphabetic(),
+            Charset::Digits => c.is_ascii_digit(),
+            Charset::Hyphen => c == '-',
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub(crate) struct Tld {
+    pub(crate) name: String,
+    #[serde(default = "default_max_length")]
+    pub(crate) max_length: usize,
+    #[serde(default = "default_charset")]
+    pub(crate) charset: Vec<Charset>,
+    #[serde(default)]
+    pub(crate) reserved: Vec<String>,
+    #[serde(default = "default_open")]
+    pub(crate) open: bool,
+    #[serde(default)]
+    pub(crate) key_required: bool,
+}
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize, clap::Args)]
+pub(crate) struct TldUpdate {
+    /// Longest allowed name
+    #[arg(long)]
+    pub(crate) max_length: Option<usize>,
+    /// Characters allowed in names, comma separated
+    #[arg(long, value_delimiter = ',')]
+    pub(crate) charset: Option<Vec<Charset>>,
+    /// Names only keys with claim-reserved may register, '*' wildcards allowed, comma separated (replaces the list)
+    #[arg(long, value_delimiter = ',')]
+    pub(crate) reserved: Option<Vec<String>>,
+    /// Whether new registrations are accepted
+    #[arg(long)]
+    pub(crate) open: Option<bool>,
+    /// Whether registering requires an API key
+    #[arg(long)]
+    pub(crate) key_required: Option<bool>,
+}
+
+fn default_max_length() -> usize { 24 }
+fn default_charset() -> Vec<Charset> { vec![Charset::Letters, Charset::Hyphen] }
+fn default_open() -> bool { true }
+
+impl Tld {
+    pub(crate) fn new(name: &str) -> Self {
+        Tld {
+            name: name.to_lowercase(),
+            max_length: default_max_length(),
+            charset: default_charset(),
+            reserved: vec![],
+            open: default_open(),
+            key_required: false,
+        }
+    }
+
+    pub(crate) fn apply(&mut self, update: TldUpdate) {
+        let TldUpdate { max_length, charset, reserved, open, key_required } = update;
+
+        self.max_length = max_length.unwrap_or(self.max_length);
+        self.charset = charset.unwrap_or(std::mem::take(&mut self.charset));
+        self.reserved = reserved.map(|names| names.iter().map(|name| name.trim().to_lowercase()).collect()).unwrap_or(std::mem::take(&mut self.reserved));
+        self.open = open.unwrap_or(self.open);
+        self.key_required = key_required.unwrap_or(self.key_required);
+    }
+
+    pub(crate) fn validate(&self) -> Result<(), String> {
+        let valid_label = Regex::new(r"^[a-z0-9]([a-z0-9-]{0,61}[a-z0-9])?$").unwrap();
+
+        if !valid_label.is_match(&self.name) {
+            return Err("TLD names may only contain lowercase letters, digits and '-'.".into());
+        }
+
+        if !(1..=63).contains(&self.max_length) {
+            return Err("max_length must be between 1 and 63.".into());
+        }
+
+        if self.charset.is_empty() {
+            return Err("charset must allow at least one character class.".into());
+        }
+
+        Ok(())
+    }
+}
+
+pub(crate) fn valid_target(target: &str) -> bool {
+    let valid_url = Regex::new(r"(?i)\bhttps?://[-a-z0-9+&@#/%?=~_|!:,.;]*[-a-z0-9+&@#/%=~_|]").unwrap();
+    target.parse::<Ipv4Addr>().is_ok() || target.parse::<Ipv6Addr>().is_ok() || valid_url.is_match(target)
+}
+
+pub(crate) fn check_record(record: &DomainRecord) -> Result<(), Invalid> {
+    let valid_url = Regex::new(r"(?i)\bhttps?://[-a-z0-9+&@#/%?=~_|!:,.;]*[-a-z0-9+&@#/%=~_|]").unwrap();
+    let valid_host = Regex::new(r"(?i)^[a-z0-9-]{1,63}(\.[a-z0-9-]{1,63})+$").unwrap();
+
+    let valid = match record.kind {
+        RecordKind::A => record.value.parse::<Ipv4Addr>().is_ok(),
+        RecordKind::AAAA => record.value.parse::<Ipv6Addr>().is_ok(),
+        RecordKind::URL => valid_url.is_match(&record.value),
+        RecordKind::TXT => !record.value.is_empty() && record.value.len() <= 255,
+        RecordKind::MX => valid_host.is_match(&record.value),
+    };
+
+    match (valid, (60..=86400).contains(&record.ttl)) {
+        (false, _) => Err(Invalid::Record(record.value.clone(), record.kind)),
+        (_, false) => Err(Invalid::Ttl),
+        _ => Ok(()),
+    }
+}
+
+pub(crate) fn check_subdomain(subdomain: &Subdomain, config: &Config) -> Result<(), Invalid> {
+    let valid_label = Regex::new(r"^[a-z0-9]([a-z0-9-]{0,61}[a-z0-9])?$").unwrap();
+    let labels: Vec<&str> = subdomain.name.split('.').collect();
+
+    if labels.len() > 3 || !labels.iter().all(|label| valid_label.is_match(label)) {
+        return Err(Invalid::Subdomain);
+    }
+
+    if Checker::new(config).check(&subdomain.name).is_some() {
+        return Err(Invalid::OffensiveSubdomain);
+    }
+
+    check_record(&DomainRecord::primary(&subdomain.ip))
+}
+
+/// Checks the records and subdomains a domain is registered with, for both the API and imports.
+pub(crate) fn check_contents(domain: &Domain, config: &Config) -> Result<(), Invalid> {
+    if domain.records.len() > MAX_RECORDS {
+        return Err(Invalid::Records);
+    }
+
+    if domain.subdomains.len() > MAX_SUBDOMAINS {
+        return Err(Invalid::Subdomains);
+    }
+
+    domain.records.iter().try_for_each(check_record)?;
+    domain.subdomains.iter().try_for_each(|subdomain| check_subdomain(subdomain, config))
+}
+
+/// Checks a name given in either Unicode or ACE form and returns the ACE (`xn--`) form to store.
+pub(crate) fn check_name(name: &str, tld: Option<&Tld>, config: &Config) -> Result<String, Invalid> {
+    let Some(tld) = tld else {
+        return Err(Invalid::Tld);
+    };
+
+    let ascii = idna::domain_to_ascii(name).map_err(|_| Invalid::Name(tld.max_length))?;
+    let unicode = to_unicode(&ascii);
+
+    if !unicode.chars().all(|c| tld.charset.iter().any(|charset| charset.allows(c))) || unicode.chars().count() > tld.max_length || ascii.len() > 63 {
+        return Err(Invalid::Name(tld.max_length));
+    }
+
+    if !unicode.is_single_script() {
+        return Err(Invalid::Confusable);
+    }
+
+    if Checker::new(config).check(&unicode).is_some() {
+        return Err(Invalid::Offensive);
+    }
+
+    Ok(ascii)
+}
+
+/// `check_name` plus the reserved lists, shared by `POST /domain` and `domain import`. Reserved names pass with `claim_reserved`.
+pub(crate) fn check_registration(name: &str, tld: Option<&Tld>, config: &Config, claim_reserved: bool) -> Result<String, Invalid> {
+    let ascii = check_name(name, tld, config)?;
+
+    match tld.is_some_and(|tld| is_reserved(&ascii, tld, config)) && !claim_reserved {
+        true => Err(Invalid::Reserved),
+        false => Ok(ascii),
+    }
+}
+
+/// The ACE form of a name for lookups, falling back to the lowercased input when it is not valid IDNA.
+pub(crate) fn to_ascii(name: &str) -> String { idna::domain_to_ascii(name).unwrap_or_else(|_| name.to_lowercase()) }
+
+pub(crate) fn to_unicode(name: &str) -> String { idna::domain_to_unicode(name).0 }
+
+/// Whether `name.tld` matches the TLD's reserved list or the config list, where entries without a dot apply to every TLD.
+pub(crate) fn is_reserved(name: &str, tld: &Tld, config: &Config) -> bool {
+    let unicode = to_unicode(name);
+
+    [name, unicode.as_str()].iter().any(|name| {
+        let fqdn = format!("{name}.{}", tld.name);
+
+        tld.reserved.iter().any(|pattern| matches(pattern, name))
+            || config.reserved().iter().any(|pattern| match pattern.contains('.') {
+                true => matches(pattern, &fqdn),
+                false => matches(pattern, name),
+            })
+    })
+}
+
+fn matches(pattern: &str, value: &str) -> bool {
+    let pattern = pattern.trim().to_lowercase();
+    let regex = format!("^{}$", pattern.split('*').map(regex::escape).collect::<Vec<_>>().join(".*"));
+    Regex::new(&regex).is_ok_and(|regex| regex.is_match(value))
+}
+
+pub(crate) fn split(domain: &str) -> Option<(String, String)> {
+    domain.trim().trim_end_matches('.').rsplit_once('.').map(|(name, tld)| (to_ascii(name), tld.to_lowercase()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unicode_and_ace_names_store_the_same_form() {
+        let (config, tld) = (Config::new(), Tld::new("it"));
+
+        assert_eq!(check_name("münchen", Some(&tld), &config), Ok("xn--mnchen-3ya".into()));
+        assert_eq!(check_name("xn--mnchen-3ya", Some(&tld), &config), Ok("xn--mnchen-3ya".into()));
+        assert_eq!(check_name("MySite", Some(&tld), &config), Ok("mysite".into()));
+        assert_eq!(to_unicode("xn--mnchen-3ya"), "münchen");
+        assert_eq!(split("München.IT"), Some(("xn--mnchen-3ya".into(), "it".into())));
+    }
+
+    #[test]
+    fn mixed_scripts_are_confusable() {
+        let (config, tld) = (Config::new(), Tld::new("it"));
+
+        assert_eq!(check_name("pаypal", Some(&tld), &config), Err(Invalid::Confusable));
+        assert_eq!(check_name("gοogle", Some(&tld), &config), Err(Invalid::Confusable));
+        assert!(check_name("пример", Some(&tld), &config).is_ok());
+        assert!(check_name("paypal", Some(&tld), &config).is_ok());
+    }
+
+    #[test]
+    fn names_follow_the_tld_policy() {
+        let config = Config::new();
+        let mut tld = Tld::new("it");
+        tld.max_length = 5;
+
+        assert_eq!(check_name("site", None, &config), Err(Invalid::Tld));
+        assert_eq!(check_name("site1", Some(&tld), &config), Err(Invalid::Name(5)));
+        assert_eq!(check_name("toolong", Some(&tld), &config), Err(Invalid::Name(5)));
+        assert_eq!(check_name("my-site", Some(&tld), &config), Err(Invalid::Name(5)));
+        assert_eq!(check_name("porn", Some(&tld), &config), Err(Invalid::Offensive));
+        assert!(check_name("a-b", Some(&tld), &config).is_ok());
+    }
+
+    #[test]
+    fn reserved_names_need_the_claim() {
+        let mut config = Config::new();
+        config.settings.reserved = vec!["google".into(), "shop.it".into()];
+        let mut tld = Tld::new("it");
+        tld.reserved = vec!["admin*".into()];
+
+        assert_eq!(check_registration("administrator", Some(&tld), &config, false), Err(Invalid::Reserved));
+        assert_eq!(check_registration("google", Some(&tld), &config, false), Err(Invalid::Reserved));
+        assert_eq!(check_registration("shop", Some(&tld), &config, false), Err(Invalid::Reserved));
+        assert_eq!(check_registration("shop", Some(&Tld::new("dev")), &config, false), Ok("shop".into()));
+        assert_eq!(check_registration("administrator", Some(&tld), &config, true), Ok("administrator".into()));
+    }
+
+    #[test]
+    fn contents_are_checked_like_the_api() {
+        let config = Config::new();
+        let subdomain = |name: &str, ip: &str| Subdomain { name: name.into(), ip: ip.into() };
+        let mut domain = Domain { records: vec![DomainRecord::primary("1.1.1.1")], ..Default::default() };
+        assert!(check_contents(&domain, &config).is_ok());
+
+        domain.records[0].ttl = 5;
+        assert_eq!(check_contents(&domain, &config), Err(Invalid::Ttl));
+
+        domain.records = vec![DomainRecord::primary("1.1.1.1"); MAX_RECORDS + 1];
+        assert_eq!(check_contents(&domain, &config), Err(Invalid::Records));
+
+        domain.records.clear();
+        domain.subdomains = vec![subdomain("porn", "1.1.1.1")];
+        assert_eq!(check_contents(&domain, &config), Err(Invalid::OffensiveSubdomain));
+
+        domain.subdomains = vec![subdomain("Bad_Label", "1.1.1.1")];
+        assert_eq!(check_contents(&domain, &config), Err(Invalid::Subdomain));
+
+        domain.subdomains = vec![subdomain("www", "not a target")];
+        assert!(matches!(check_contents(&domain, &config), Err(Invalid::Record(..))));
+    }
+}
@@ -0,0 +1,282 @@
+use crate::{
+    changes::Change,
+    config::Config,
+    http::Domain,
+    store::{DomainStore, MirrorStore, Store},
+};
+use anyhow::Error;
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+const PAGE: u64 = 500;
+
+#[derive(Clone, Copy, Debug, Default, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) enum Conflict {
+    #[default]
+    LocalWins,
+    FirstRegistered,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub(crate) struct Peer {
+    pub(crate) name: String,
+    pub(crate) url: String,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Replication {
+    #[serde(default)]
+    pub(crate) peers: Vec<Peer>,
+    #[serde(default)]
+    pub(crate) conflict: Conflict,
+    #[serde(default = "default_interval")]
+    pub(crate) interval: u64,
+}
+
+impl Default for Replication {
+    fn default() -> Self {
+        Self {
+            peers: vec![],
+            conflict: Conflict::default(),
+            interval: default_interval(),
+        }
+    }
+}
+
+fn default_interval() -> u64 { 60 }
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub(crate) struct Mirror {
+    pub(crate) peer: String,
+    pub(crate) synced_at: i64,
+    // when this registry first saw the peer's copy, kept by `save_mirror` across syncs; peers never set it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) first_seen: Option<i64>,
+    #[serde(flatten)]
+    pub(crate) domain: Domain,
+}
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub(crate) struct PeerState {
+    pub(crate) peer: String,
+    pub(crate) cursor: u64,
+    #[serde(default)]
+    pub(crate) applied: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) last_sync_at: Option<i64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) last_ok_at: Option<i64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) last_error: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct Listing {
+    domains: Vec<Domain>,
+}
+
+pub(crate) fn spawn(store: Store, config: &Config) {
+    let replication = config.replication().clone();
+
+    if replication.peers.is_empty() {
+        return;
+    }
+
+    actix_web::rt::spawn(async move {
+        let client = match client() {
+            Ok(client) => client,
+            Err(err) => return log::error!("Failed to build replication client: {err}"),
+        };
+        let mut interval = actix_web::rt::time::interval(Duration::from_secs(replication.interval.max(10)));
+
+        loop {
+            interval.tick().await;
+            for peer in &replication.peers {
+                match sync(store.mirrors.as_ref(), &client, peer).await {
+                    Ok(0) => {}
+                    Ok(applied) => log::info!("Replication: applied {applied} changes from {}", peer.name),
+                    Err(err) => log::warn!("Replication from {} failed: {err}", peer.name),
+                }
+            }
+        }
+    });
+}
+
+pub(crate) fn client() -> Result<reqwest::Client, Error> { Ok(reqwest::Client::builder().timeout(Duration::from_secs(30)).build()?) }
+
+/// Pulls everything new from `peer` and records the outcome in its sync state.
+pub(crate) async fn sync(mirrors: &dyn MirrorStore, client: &reqwest::Client, peer: &Peer) -> Result<u64, Error> {
+    let mut state = mirrors.peer_state(&peer.name).await?.unwrap_or_else(|| PeerState {
+        peer: peer.name.clone(),
+        ..Default::default()
+    });
+
+    let result = pull(mirrors, client, peer, &mut state).await;
+    let now = chrono::Utc::now().timestamp();
+
+    state.last_sync_at = Some(now);
+    match &result {
+        Ok(_) => {
+            state.last_ok_at = Some(now);
+            state.last_error = None;
+        }
+        Err(err) => state.last_error = Some(err.to_string()),
+    }
+
+    mirrors.save_peer_state(&state).await?;
+    result
+}
+
+// The first sync copies the peer's domain list, since its change feed may not reach back to every registration.
+async fn pull(mirrors: &dyn MirrorStore, client: &reqwest::Client, peer: &Peer, state: &mut PeerState) -> Result<u64, Error> {
+    let base = peer.url.trim_end_matches('/');
+    let mut applied = 0;
+
+    if state.cursor == 0 && state.last_ok_at.is_none() {
+        for page in 1.. {
+            let listing: Listing = client.get(format!("{base}/domains?page={page}&page_size=100")).send().await?.error_for_status()?.json().await?;
+            if listing.domains.is_empty() {
+                break;
+            }
+            for domain in listing.domains {
+                mirrors.save_mirror(&mirror(&peer.name, domain)).await?;
+                applied += 1;
+            }
+        }
+    }
+
+    loop {
+        let changes: Vec<Change> = client.get(format!("{base}/changes?since={}&limit={PAGE}", state.cursor)).send().await?.error_for_status()?.json().await?;
+        if changes.is_empty() {
+            break;
+        }
+
+        for change in changes {
+            apply(mirrors, &peer.name, &change).await?;
+            state.cursor = change.seq;
+            state.applied += 1;
+            applied += 1;
+        }
+        mirrors.save_peer_state(state).await?;
+    }
+
+    Ok(applied)
+}
+
+async fn apply(mirrors: &dyn MirrorStore, peer: &str, change: &Change) -> Result<(), Error> {
+    let after = change.after.clone().map(serde_json::from_value::<Domain>).transpose()?;
+
+    match after.filter(|domain| domain.banned.is_none()) {
+        Some(domain) => mirrors.save_mirror(&mirror(peer, domain)).await,
+        None => match change.domain.rsplit_once('.') {
+            Some((name, tld)) => mirrors.delete_mirror(peer, name, tld).await.map(drop),
+            None => Ok(()),
+        },
+    }
+}
+
+fn mirror(peer: &str, domain: Domain) -> Mirror {
+    let now = chrono::Utc::now().timestamp();
+
+    Mirror {
+        peer: peer.to_string(),
+        synced_at: now,
+        first_seen: Some(now),
+        domain,
+    }
+}
+
+/// The peer holding an active copy of a name that, under `first-registered`, a new local registration must give way to.
+pub(crate) async fn held_by(mirrors: &dyn MirrorStore, conflict: Conflict, name: &str, tld: &str) -> Result<Option<String>, Error> {
+    if conflict != Conflict::FirstRegistered {
+        return Ok(None);
+    }
+
+    let now = chrono::Utc::now().timestamp();
+    Ok(mirrors.find_mirrors(name, tld).await?.into_iter().find(|mirror| mirror.domain.is_active(now)).map(|mirror| mirror.peer))
+}
+
+/// Resolves a name against the local registry and every mirror, returning the winner and the peer it came from.
+pub(crate) async fn resolve(db: &dyn DomainStore, mirrors: &dyn MirrorStore, conflict: Conflict, name: &str, tld: &str) -> Result<Option<(Domain, Option<String>)>, Error> {
+    let local = db.resolve(name, tld).await?;
+
+    if local.is_some() && conflict == Conflict::LocalWins {
+        return Ok(local.map(|domain| (domain, None)));
+    }
+
+    let now = chrono::Utc::now().timestamp();
+    let (sub, parent) = match name.rsplit_once('.') {
+        Some((sub, parent)) => (Some(sub), parent),
+        None => (None, name),
+    };
+
+    let mirrored = mirrors.find_mirrors(parent, tld).await?.into_iter().filter_map(|mirror| {
+        let domain = match sub {
+            Some(sub) => mirror.domain.subdomain(sub),
+            None => Some(mirror.domain),
+        };
+        // the peer's own `created_at` is not trusted, so a mirror counts from when it was first seen here
+        let since = mirror.first_seen.unwrap_or(i64::MAX);
+        domain.filter(|domain| domain.is_active(now)).map(|domain| (domain, Some(mirror.peer), since))
+    });
+
+    // local comes first so it wins ties, and an unknown local registration time counts as oldest
+    let local = local.map(|domain| {
+        let since = domain.created_at.unwrap_or(i64::MIN);
+        (domain, None, since)
+    });
+    let winner = local.into_iter().chain(mirrored).min_by_key(|(_, _, since)| *since).map(|(domain, peer, _)| (domain, peer));
+
+    Ok(winner)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::Embedded;
+
+    fn domain(ip: &str, created_at: i64) -> Domain {
+        Domain {
+            name: "bank".into(),
+            tld: "it".into(),
+            ip: ip.into(),
+            created_at: Some(created_at),
+            ..Default::default()
+        }
+    }
+
+    fn store(name: &str) -> (Embedded, String) {
+        let path = std::env::temp_dir().join(format!("webx-replication-{name}-{}", std::process::id())).to_string_lossy().into_owned();
+        (Embedded::open(&path).unwrap(), path)
+    }
+
+    #[actix_web::test]
+    async fn peers_cannot_backdate_their_copies() {
+        let (store, path) = store("backdate");
+        let now = chrono::Utc::now().timestamp();
+        store.insert(&domain("1.1.1.1", now - 60)).await.unwrap();
+        store.save_mirror(&mirror("evil", domain("6.6.6.6", 0))).await.unwrap();
+
+        let winner = resolve(&store, &store, Conflict::FirstRegistered, "bank", "it").await.unwrap();
+        std::fs::remove_dir_all(&path).unwrap();
+
+        assert_eq!(winner.map(|(domain, peer)| (domain.ip, peer)), Some(("1.1.1.1".into(), None)));
+    }
+
+    #[actix_web::test]
+    async fn first_seen_survives_later_syncs() {
+        let (store, path) = store("first-seen");
+        let first = Mirror { first_seen: Some(100), ..mirror("peer", domain("1.1.1.1", 0)) };
+        store.save_mirror(&first).await.unwrap();
+        store.save_mirror(&mirror("peer", domain("2.2.2.2", 0))).await.unwrap();
+
+        let saved = store.find_mirrors("bank", "it").await.unwrap();
+        let winner = resolve(&store, &store, Conflict::FirstRegistered, "bank", "it").await.unwrap();
+        std::fs::remove_dir_all(&path).unwrap();
+
+        assert_eq!(saved.len(), 1);
+        assert_eq!((saved[0].first_seen, saved[0].domain.ip.as_str()), (Some(100), "2.2.2.2"));
+        assert_eq!(winner.and_then(|(_, peer)| peer), Some("peer".into()));
+    }
+}
@@ -0,0 +1,391 @@
+use crate::{
+    config::Config,
+    http::{Domain, DomainRecord, RecordKind},
+    replication::{self, Conflict},
+    store::{ChangeStore, DomainStore, MirrorStore, TldStore},
+    zone,
+};
+use anyhow::Error;
+use hickory_proto::{
+    op::{Edns, Message, MessageType, OpCode, ResponseCode},
+    rr::{
+        rdata::{A, AAAA, CNAME, MX, SOA, TXT},
+        Name, RData, Record, RecordType,
+    },
+};
+use std::sync::Arc;
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream, UdpSocket},
+};
+
+// the largest UDP answer sent to EDNS clients, the commonly used size that avoids IP fragmentation
+const MAX_UDP_PAYLOAD: u16 = 1232;
+
+pub(crate) struct Resolver {
+    db: Arc<dyn DomainStore>,
+    tlds: Arc<dyn TldStore>,
+    changes: Arc<dyn ChangeStore>,
+    mirrors: Arc<dyn MirrorStore>,
+    conflict: Conflict,
+    ttl: u32,
+}
+
+impl Resolver {
+    pub(crate) fn new(db: Arc<dyn DomainStore>, tlds: Arc<dyn TldStore>, changes: Arc<dyn ChangeStore>, mirrors: Arc<dyn MirrorStore>, config: &Config) -> Self {
+        Self {
+            db,
+            tlds,
+            changes,
+            mirrors,
+            conflict: config.replication().conflict,
+            ttl: config.server.dns.ttl,
+        }
+    }
+
+    /// Answers a wire format query. Over UDP, answers larger than 512 bytes (or the EDNS size the client offers) are
+    /// sent without records and with the TC bit set, so the client retries over TCP.
+    pub(crate) async fn answer(&self, packet: &[u8], udp: bool) -> Result<Vec<u8>, Error> {
+        let (mut response, limit) = match Message::from_vec(packet) {
+            Ok(request) => (self.resolve(&request).await, request.max_payload().min(MAX_UDP_PAYLOAD)),
+            Err(err) => {
+                log::debug!("Malformed DNS query: {err}");
+                let id = packet.get(..2).map(|id| u16::from_be_bytes([id[0], id[1]])).unwrap_or(0);
+                (Message::error_msg(id, OpCode::Query, ResponseCode::FormErr), 512)
+            }
+        };
+
+        let encoded = response.to_vec()?;
+        if !udp || encoded.len() <= limit as usize {
+            return Ok(encoded);
+        }
+
+        response.take_answers();
+        response.take_name_servers();
+        response.take_additionals();
+        response.set_truncated(true);
+
+        Ok(response.to_vec()?)
+    }
+
+    pub(crate) async fn resolve(&self, request: &Message) -> Message {
+        let mut response = Message::new();
+
+        response
+            .set_id(request.id())
+            .set_message_type(MessageType::Response)
+            .set_op_code(request.op_code())
+            .set_recursion_desired(request.recursion_desired())
+            .add_queries(request.queries().to_vec());
+
+        if request.extensions().is_some() {
+            let mut edns = Edns::new();
+            edns.set_max_payload(MAX_UDP_PAYLOAD);
+            response.set_edns(edns);
+        }
+
+        if request.op_code() != OpCode::Query {
+            response.set_response_code(ResponseCode::NotImp);
+            return response;
+        }
+
+        let Some(query) = request.queries().first() else {
+            response.set_response_code(ResponseCode::FormErr);
+            return response;
+        };
+
+        let owner = query.name().to_lowercase();
+        let labels: Vec<String> = owner.iter().map(|label| String::from_utf8_lossy(label).into_owned()).collect();
+
+        let tld = match labels.last() {
+            Some(tld) => match self.tlds.find_tld(tld).await {
+                Ok(Some(_)) => tld,
+                Ok(None) => {
+                    response.set_response_code(ResponseCode::Refused);
+                    return response;
+                }
+                Err(err) => {
+                    log::error!("Failed to look up TLD {tld}: {err}");
+                    response.set_response_code(ResponseCode::ServFail);
+                    return response;
+                }
+            },
+            None => {
+                response.set_response_code(ResponseCode::Refused);
+                return response;
+            }
+        };
+
+        response.set_authoritative(true);
+
+        let domain = match labels.split_last() {
+            Some((tld, name)) if !name.is_empty() => match replication::resolve(self.db.as_ref(), self.mirrors.as_ref(), self.conflict, &name.join("."), tld).await {
+                Ok(domain) => domain.map(|(domain, _)| domain),
+                Err(err) => {
+                    log::error!("Failed to resolve {owner}: {err}");
+                    response.set_response_code(ResponseCode::ServFail);
+                    return response;
+                }
+            },
+            _ => None,
+        };
+
+        match domain {
+            Some(domain) => {
+                let answers = self.records(&owner, &domain, query.query_type());
+                if answers.is_empty() {
+                    response.add_name_server(self.soa(tld).await);
+                }
+                response.add_answers(answers);
+            }
+            None => {
+                if labels.len() > 1 {
+                    response.set_response_code(ResponseCode::NXDomain);
+                }
+                response.add_name_server(self.soa(tld).await);
+            }
+        }
+
+        response
+    }
+
+    // A CNAME owner holds no other data, so a URL target is answered with the CNAME alone, except for TXT and MX queries.
+    fn records(&self, owner: &Name, domain: &Domain, query_type: RecordType) -> Vec<Record> {
+        let wants = |record_type: RecordType| query_type == record_type || query_type == RecordType::ANY;
+        let records = domain.records();
+
+        if let Some(cname) = self.cname(owner, &records).filter(|_| !matches!(query_type, RecordType::TXT | RecordType::MX)) {
+            return vec![cname];
+        }
+
+        let mut answers = vec![];
+
+        for record in &records {
+            let rdata = match record.kind {
+                RecordKind::A if wants(RecordType::A) => record.value.parse().ok().map(|ip| RData::A(A(ip))),
+                RecordKind::AAAA if wants(RecordType::AAAA) => record.value.parse().ok().map(|ip| RData::AAAA(AAAA(ip))),
+                RecordKind::TXT | RecordKind::URL if wants(RecordType::TXT) => Some(RData::TXT(TXT::new(vec![record.value.clone()]))),
+                RecordKind::MX if wants(RecordType::MX) => fqdn(&record.value).map(|exchange| RData::MX(MX::new(record.priority.unwrap_or(10), exchange))),
+                _ => None,
+            };
+
+            if let Some(rdata) = rdata {
+                answers.push(Record::from_rdata(owner.clone(), record.ttl, rdata));
+            }
+        }
+
+        answers
+    }
+
+    // domains without an address record point at the host of their URL target
+    fn cname(&self, owner: &Name, records: &[DomainRecord]) -> Option<Record> {
+        if records.iter().any(|record| matches!(record.kind, RecordKind::A | RecordKind::AAAA)) {
+            return None;
+        }
+
+        let record = records.iter().find(|record| record.kind == RecordKind::URL)?;
+        let target = url::Url::parse(&record.value).ok().and_then(|url| url.host_str().and_then(fqdn))?;
+
+        Some(Record::from_rdata(owner.clone(), record.ttl, RData::CNAME(CNAME(target))))
+    }
+
+    // the serial follows the change feed, so secondaries see every change as a new zone version
+    async fn soa(&self, tld: &str) -> Record {
+        let zone = Name::from_ascii(format!("{tld}.")).unwrap_or_else(|_| Name::root());
+        let rname = Name::from_ascii(format!("hostmaster.{tld}.")).unwrap_or_else(|_| Name::root());
+        let serial = match self.changes.last_seq().await {
+            Ok(seq) => zone::serial(seq),
+            Err(err) => {
+                log::error!("Failed to read change seq for SOA serial: {err}");
+                0
+            }
+        };
+
+        Record::from_rdata(zone.clone(), self.ttl, RData::SOA(SOA::new(zone, rname, serial, 3600, 600, 86400, self.ttl)))
+    }
+}
+
+fn fqdn(host: &str) -> Option<Name> { Name::from_ascii(format!("{}.", host.trim_end_matches('.'))).ok() }
+
+pub(crate) async fn serve(resolver: Arc<Resolver>, address: String) -> std::io::Result<()> {
+    let socket = Arc::new(UdpSocket::bind(&address).await?);
+    let listener = TcpListener::bind(&address).await?;
+
+    actix_web::rt::spawn({
+        let resolver = resolver.clone();
+        async move {
+            let mut buffer = [0u8; 4096];
+            loop {
+                let (len, peer) = match socket.recv_from(&mut buffer).await {
+                    Ok(res) => res,
+                    Err(err) => {
+                        log::warn!("DNS udp receive failed: {err}");
+                        continue;
+                    }
+                };
+
+                match resolver.answer(&buffer[..len], true).await {
+                    Ok(response) => drop(socket.send_to(&response, peer).await),
+                    Err(err) => log::warn!("DNS udp answer to {peer} failed: {err}"),
+                }
+            }
+        }
+    });
+
+    actix_web::rt::spawn(async move {
+        loop {
+            match listener.accept().await {
+                Ok((stream, peer)) => {
+                    let resolver = resolver.clone();
+                    actix_web::rt::spawn(async move {
+                        if let Err(err) = serve_tcp(stream, resolver).await {
+                            log::debug!("DNS tcp connection from {peer} closed: {err}");
+                        }
+                    });
+                }
+                Err(err) => log::warn!("DNS tcp accept failed: {err}"),
+            }
+        }
+    });
+
+    log::info!("DNS listening on {address} (udp/tcp)");
+    Ok(())
+}
+
+async fn serve_tcp(mut stream: TcpStream, resolver: Arc<Resolver>) -> Result<(), Error> {
+    loop {
+        let len = match stream.read_u16().await {
+            Ok(len) => len as usize,
+            Err(err) if err.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(()),
+            Err(err) => return Err(err.into()),
+        };
+
+        let mut packet = vec![0u8; len];
+        stream.read_exact(&mut packet).await?;
+
+        let response = resolver.answer(&packet, false).await?;
+        stream.write_u16(response.len() as u16).await?;
+        stream.write_all(&response).await?;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{changes::Change, http::Subdomain, registry::Tld, store::Embedded, webhook::Event};
+    use hickory_proto::op::Query;
+
+    async fn resolver(name: &str, domains: &[Domain]) -> (Resolver, String) {
+        let path = std::env::temp_dir().join(format!("webx-resolver-{name}-{}", std::process::id())).to_string_lossy().into_owned();
+        let store = Arc::new(Embedded::open(&path).unwrap());
+
+        store.save_tld(&Tld::new("it")).await.unwrap();
+        for domain in domains {
+            store.insert(domain).await.unwrap();
+        }
+
+        (Resolver::new(store.clone(), store.clone(), store.clone(), store, &Config::new()), path)
+    }
+
+    fn domain(name: &str, ip: &str, records: Vec<DomainRecord>) -> Domain {
+        let mut domain = Domain { name: name.into(), tld: "it".into(), ip: ip.into(), records, ..Default::default() };
+        domain.set_primary(ip);
+        domain
+    }
+
+    fn txt(value: &str) -> DomainRecord {
+        DomainRecord {
+            kind: RecordKind::TXT,
+            value: value.into(),
+            ..DomainRecord::primary("1.1.1.1")
+        }
+    }
+
+    fn query(name: &str, query_type: RecordType) -> Vec<u8> {
+        let mut message = Message::new();
+        message.set_id(7).add_query(Query::query(Name::from_ascii(name).unwrap(), query_type));
+        message.to_vec().unwrap()
+    }
+
+    async fn ask(resolver: &Resolver, name: &str, query_type: RecordType, udp: bool) -> Message {
+        Message::from_vec(&resolver.answer(&query(name, query_type), udp).await.unwrap()).unwrap()
+    }
+
+    #[actix_web::test]
+    async fn udp_answers_that_do_not_fit_are_truncated() {
+        let records = (0..8).map(|i| txt(&format!("{i}{}", "x".repeat(200)))).collect();
+        let (resolver, path) = resolver("truncate", &[domain("big", "1.1.1.1", records)]).await;
+
+        let udp = ask(&resolver, "big.it.", RecordType::TXT, true).await;
+        let tcp = ask(&resolver, "big.it.", RecordType::TXT, false).await;
+        std::fs::remove_dir_all(&path).unwrap();
+
+        assert!(udp.truncated());
+        assert!(udp.answers().is_empty());
+        assert!(!tcp.truncated());
+        assert_eq!(tcp.answers().len(), 8);
+    }
+
+    #[actix_web::test]
+    async fn url_targets_answer_with_a_cname_alone() {
+        let (resolver, path) = resolver("cname", &[domain("site", "https://example.com/home", vec![txt("hello")])]).await;
+
+        let a = ask(&resolver, "site.it.", RecordType::A, true).await;
+        let any = ask(&resolver, "site.it.", RecordType::ANY, true).await;
+        let txt = ask(&resolver, "site.it.", RecordType::TXT, true).await;
+        std::fs::remove_dir_all(&path).unwrap();
+
+        for response in [&a, &any] {
+            assert_eq!(response.answers().len(), 1);
+            assert_eq!(response.answers()[0].data(), Some(&RData::CNAME(CNAME(Name::from_ascii("example.com.").unwrap()))));
+        }
+        assert_eq!(txt.answers().len(), 2);
+        assert!(txt.answers().iter().all(|record| record.record_type() == RecordType::TXT));
+    }
+
+    #[actix_web::test]
+    async fn unknown_names_get_nxdomain_and_the_soa() {
+        let (resolver, path) = resolver("nxdomain", &[domain("site", "1.1.1.1", vec![])]).await;
+        for _ in 0..3 {
+            let change = Change { seq: 0, event: Event::Create, action: "create".into(), domain: "site.it".into(), at: 0, after: None };
+            resolver.changes.append_change(change).await.unwrap();
+        }
+
+        let missing = ask(&resolver, "missing.it.", RecordType::A, true).await;
+        let empty = ask(&resolver, "site.it.", RecordType::MX, true).await;
+        let unknown_tld = ask(&resolver, "site.zz.", RecordType::A, true).await;
+        std::fs::remove_dir_all(&path).unwrap();
+
+        assert_eq!(missing.response_code(), ResponseCode::NXDomain);
+        assert!(missing.authoritative());
+        match missing.name_servers().first().and_then(|record| record.data()) {
+            Some(RData::SOA(soa)) => assert_eq!(soa.serial(), 3),
+            other => panic!("expected an SOA record, got {other:?}"),
+        }
+        assert_eq!(empty.response_code(), ResponseCode::NoError);
+        assert!(empty.answers().is_empty());
+        assert_eq!(empty.name_servers().len(), 1);
+        assert_eq!(unknown_tld.response_code(), ResponseCode::Refused);
+    }
+
+    #[actix_web::test]
+    async fn subdomains_are_answered() {
+        let mut bank = domain("bank", "1.1.1.1", vec![]);
+        bank.subdomains = vec![Subdomain { name: "api".into(), ip: "2.2.2.2".into() }];
+        let (resolver, path) = resolver("subdomain", &[bank]).await;
+
+        let api = ask(&resolver, "api.bank.it.", RecordType::A, true).await;
+        let www = ask(&resolver, "www.bank.it.", RecordType::A, true).await;
+        std::fs::remove_dir_all(&path).unwrap();
+
+        assert_eq!(api.answers()[0].data(), Some(&RData::A("2.2.2.2".parse().unwrap())));
+        assert_eq!(www.response_code(), ResponseCode::NXDomain);
+    }
+
+    #[test]
+    fn serials_wrap_around() {
+        assert_eq!(zone::serial(42), 42);
+        assert_eq!(zone::serial(u32::MAX as u64), u32::MAX);
+        assert_eq!(zone::serial(u32::MAX as u64 + 5), 4);
+    }
+}
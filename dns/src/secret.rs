@@ -1,6 +1,8 @@
 use rand::{rngs::StdRng, Rng, SeedableRng};
 use rhai::{Engine, Scope};
+use sha2::{Digest, Sha256};
 use std::path::PathBuf;
+use subtle::ConstantTimeEq;
 
 pub fn generate(size: usize) -> String {
     const ALPHABET: &[u8] = b"0123456789abcdefghijklmnopqrstuvwxyzABCDEFGHIJKLMNOPQRSTUVWXYZ";
@@ -27,6 +29,26 @@ pub fn generate(size: usize) -> String {
     id
 }
 
+pub fn record_id() -> String { generate(12) }
+
+pub fn key_id(key: &str) -> String { hex::encode(&Sha256::digest(key.as_bytes())[..8]) }
+
+pub fn is_hashed(value: &str) -> bool { value.starts_with("sha256$") }
+
+pub fn hash(key: &str) -> String {
+    let salt = generate(16);
+    format!("sha256${salt}${}", hex::encode(Sha256::digest(format!("{salt}{key}"))))
+}
+
+pub fn verify(key: &str, hash: &str) -> bool {
+    let Some((salt, digest)) = hash.strip_prefix("sha256$").and_then(|rest| rest.split_once('$')) else {
+        return false;
+    };
+
+    let computed = hex::encode(Sha256::digest(format!("{salt}{key}")));
+    computed.as_bytes().ct_eq(digest.as_bytes()).into()
+}
+
 pub fn execute_rhai_script(path: String) -> String {
     let engine = Engine::new();
     let mut scope = Scope::new();
@@ -37,3 +59,55 @@ pub fn execute_rhai_script(path: String) -> String {
         Err(e) => e.to_string(),
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hash_verifies_its_key() {
+        let hashed = hash("correct horse");
+
+        assert!(is_hashed(&hashed));
+        assert!(verify("correct horse", &hashed));
+        assert!(!verify("correct horse ", &hashed));
+        assert!(!verify("", &hashed));
+    }
+
+    #[test]
+    fn hash_is_salted() {
+        let (first, second) = (hash("key"), hash("key"));
+
+        assert_ne!(first, second);
+        assert!(verify("key", &first) && verify("key", &second));
+    }
+
+    #[test]
+    fn legacy_plaintext_is_not_a_hash() {
+        assert!(!is_hashed("plainkey"));
+        assert!(!verify("plainkey", "plainkey"));
+    }
+
+    #[test]
+    fn malformed_hashes_never_verify() {
+        assert!(!verify("key", "sha256$"));
+        assert!(!verify("key", "sha256$saltonly"));
+        assert!(!verify("key", "md5$salt$digest"));
+    }
+
+    #[test]
+    fn key_id_is_stable_per_key() {
+        assert_eq!(key_id("key"), key_id("key"));
+        assert_ne!(key_id("key"), key_id("other"));
+        assert_eq!(key_id("key").len(), 16);
+    }
+
+    #[test]
+    fn generate_uses_the_alphabet() {
+        let id = generate(31);
+
+        assert_eq!(id.len(), 31);
+        assert!(id.chars().all(|c| c.is_ascii_alphanumeric()));
+        assert_ne!(id, generate(31));
+    }
+}
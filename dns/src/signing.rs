@@ -0,0 +1,121 @@
+use anyhow::{anyhow, Error};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use ed25519_dalek::{Signer as _, SigningKey};
+use rand::{rngs::OsRng, RngCore};
+use serde::{Deserialize, Serialize};
+use std::{
+    fs::{self, OpenOptions},
+    io::Write,
+};
+#[cfg(unix)]
+use std::os::unix::fs::OpenOptionsExt;
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct Signing {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) key_file: Option<String>,
+}
+
+pub(crate) struct Signer {
+    key: SigningKey,
+}
+
+impl Signer {
+    /// Reads a base64 encoded 32 byte Ed25519 seed.
+    pub(crate) fn load(path: &str) -> Result<Self, Error> {
+        let seed = STANDARD.decode(fs::read_to_string(path)?.trim())?;
+        let seed: [u8; 32] = seed.try_into().map_err(|_| anyhow!("{path} does not hold a 32 byte Ed25519 seed"))?;
+
+        Ok(Signer { key: SigningKey::from_bytes(&seed) })
+    }
+
+    /// Creates a new key at `path`, readable by the owner only and never replacing an existing one.
+    pub(crate) fn generate(path: &str) -> Result<Self, Error> {
+        let mut options = OpenOptions::new();
+        options.write(true).create_new(true);
+        #[cfg(unix)]
+        options.mode(0o600);
+
+        let mut file = match options.open(path) {
+            Ok(file) => file,
+            Err(err) if err.kind() == std::io::ErrorKind::AlreadyExists => return Err(anyhow!("{path} already exists")),
+            Err(err) => return Err(err.into()),
+        };
+
+        let mut seed = [0u8; 32];
+        OsRng.fill_bytes(&mut seed);
+        file.write_all((STANDARD.encode(seed) + "\n").as_bytes())?;
+        file.sync_all()?;
+
+        Ok(Signer { key: SigningKey::from_bytes(&seed) })
+    }
+
+    pub(crate) fn public_key(&self) -> String { STANDARD.encode(self.key.verifying_key().as_bytes()) }
+
+    /// Signs `body` as sent, for the `x-webx-signature` header.
+    pub(crate) fn sign(&self, body: &[u8]) -> String { format!("ed25519={}", STANDARD.encode(self.key.sign(body).to_bytes())) }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ed25519_dalek::{Signature, VerifyingKey};
+
+    fn key_file(name: &str) -> String { std::env::temp_dir().join(format!("webx-signing-{name}-{}", std::process::id())).to_string_lossy().into_owned() }
+
+    fn verify(public_key: &str, body: &[u8], header: &str) -> bool {
+        let key: [u8; 32] = STANDARD.decode(public_key).unwrap().try_into().unwrap();
+        let signature = STANDARD.decode(header.strip_prefix("ed25519=").unwrap()).unwrap();
+
+        VerifyingKey::from_bytes(&key).unwrap().verify_strict(body, &Signature::from_slice(&signature).unwrap()).is_ok()
+    }
+
+    #[test]
+    fn signatures_verify_against_the_public_key() {
+        let path = key_file("verify");
+        let signer = Signer::generate(&path).unwrap();
+        let header = signer.sign(br#"{"ip":"1.1.1.1"}"#);
+        fs::remove_file(&path).unwrap();
+
+        assert!(verify(&signer.public_key(), br#"{"ip":"1.1.1.1"}"#, &header));
+        assert!(!verify(&signer.public_key(), br#"{"ip":"6.6.6.6"}"#, &header));
+    }
+
+    #[test]
+    fn generated_keys_load_back() {
+        let path = key_file("load");
+        let generated = Signer::generate(&path).unwrap();
+        let loaded = Signer::load(&path);
+        let again = Signer::generate(&path);
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(loaded.unwrap().public_key(), generated.public_key());
+        assert!(again.is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn generated_keys_are_private() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let path = key_file("mode");
+        Signer::generate(&path).unwrap();
+        let mode = fs::metadata(&path).unwrap().permissions().mode();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(mode & 0o777, 0o600);
+    }
+
+    #[test]
+    fn load_rejects_other_files() {
+        let path = key_file("invalid");
+        fs::write(&path, STANDARD.encode([0u8; 16])).unwrap();
+        let short = Signer::load(&path);
+        fs::write(&path, "not base64").unwrap();
+        let garbage = Signer::load(&path);
+        fs::remove_file(&path).unwrap();
+
+        assert!(short.is_err() && garbage.is_err());
+        assert!(Signer::load(&path).is_err());
+    }
+}
@@ -0,0 +1,173 @@
+mod embedded;
+mod mongo;
+
+use crate::{
+    audit::{self, AuditEntry},
+    changes::Change,
+    health::DomainHealth,
+    http::Domain,
+    registry::Tld,
+    replication::{Mirror, PeerState},
+    secret,
+    webhook::{Delivery, Outbox, Status},
+};
+use anyhow::Error;
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+
+pub(crate) use embedded::Embedded;
+pub(crate) use mongo::Mongo;
+
+#[derive(Clone, Copy, Debug, Default, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Backend {
+    #[default]
+    Mongo,
+    Embedded,
+}
+
+/// The domain was changed by someone else after it was read, so the update was not applied.
+#[derive(Debug)]
+pub(crate) struct Stale;
+
+impl std::fmt::Display for Stale {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result { write!(f, "Domain was changed by another request") }
+}
+
+impl std::error::Error for Stale {}
+
+#[derive(Clone)]
+pub(crate) struct Store {
+    pub(crate) domains: Arc<dyn DomainStore>,
+    pub(crate) audit: Arc<dyn AuditStore>,
+    pub(crate) tlds: Arc<dyn TldStore>,
+    pub(crate) mirrors: Arc<dyn MirrorStore>,
+    pub(crate) health: Arc<dyn HealthStore>,
+    pub(crate) outbox: Outbox,
+}
+
+#[async_trait]
+pub(crate) trait DomainStore: Send + Sync {
+    async fn find(&self, name: &str, tld: &str) -> Result<Option<Domain>, Error>;
+    async fn find_by_key(&self, key_id: &str) -> Result<Option<Domain>, Error>;
+    async fn insert(&self, domain: &Domain) -> Result<(), Error>;
+    /// Replaces the domain if its stored `updated_at` is still `expected`, failing with `Stale` when it is not.
+    async fn update(&self, domain: &Domain, expected: Option<i64>) -> Result<bool, Error>;
+    async fn delete(&self, name: &str, tld: &str) -> Result<bool, Error>;
+    async fn list(&self, skip: u64, limit: u64) -> Result<Vec<Domain>, Error>;
+    /// Lists domains that are not banned, paginating after they are filtered out.
+    async fn list_public(&self, skip: u64, limit: u64) -> Result<Vec<Domain>, Error>;
+    async fn search(&self, query: &str, tld: Option<&str>, skip: u64, limit: u64) -> Result<Vec<Domain>, Error>;
+
+    async fn resolve(&self, name: &str, tld: &str) -> Result<Option<Domain>, Error> {
+        let domain = match name.rsplit_once('.') {
+            Some((sub, parent)) => self.find(parent, tld).await?.and_then(|domain| domain.subdomain(sub)),
+            None => self.find(name, tld).await?,
+        };
+
+        Ok(domain.filter(|domain| domain.is_active(chrono::Utc::now().timestamp())))
+    }
+}
+
+#[async_trait]
+pub(crate) trait TldStore: Send + Sync {
+    async fn tlds(&self) -> Result<Vec<Tld>, Error>;
+    async fn find_tld(&self, name: &str) -> Result<Option<Tld>, Error>;
+    async fn save_tld(&self, tld: &Tld) -> Result<(), Error>;
+    async fn delete_tld(&self, name: &str) -> Result<bool, Error>;
+    /// Records that the config TLDs have been imported. Only the first call returns `true`.
+    async fn mark_tlds_imported(&self) -> Result<bool, Error>;
+}
+
+#[async_trait]
+pub(crate) trait AuditStore: Send + Sync {
+    async fn append(&self, entry: &AuditEntry) -> Result<(), Error>;
+    async fn query(&self, domain: Option<&str>, skip: u64, limit: u64) -> Result<Vec<AuditEntry>, Error>;
+}
+
+#[async_trait]
+pub(crate) trait DeliveryStore: Send + Sync {
+    async fn save_delivery(&self, delivery: &Delivery) -> Result<(), Error>;
+    async fn find_delivery(&self, id: &str) -> Result<Option<Delivery>, Error>;
+    async fn due_deliveries(&self, now: i64, limit: u64) -> Result<Vec<Delivery>, Error>;
+    async fn deliveries(&self, status: Option<Status>, skip: u64, limit: u64) -> Result<Vec<Delivery>, Error>;
+}
+
+#[async_trait]
+pub(crate) trait ChangeStore: Send + Sync {
+    async fn append_change(&self, change: Change) -> Result<u64, Error>;
+    async fn changes_since(&self, seq: u64, limit: u64) -> Result<Vec<Change>, Error>;
+    /// The seq of the newest change, or 0 when there are none.
+    async fn last_seq(&self) -> Result<u64, Error>;
+    /// Deletes all but the newest `keep` changes and returns how many were deleted.
+    async fn prune_changes(&self, keep: u64) -> Result<u64, Error>;
+}
+
+#[async_trait]
+pub(crate) trait MirrorStore: Send + Sync {
+    async fn find_mirrors(&self, name: &str, tld: &str) -> Result<Vec<Mirror>, Error>;
+    async fn save_mirror(&self, mirror: &Mirror) -> Result<(), Error>;
+    async fn delete_mirror(&self, peer: &str, name: &str, tld: &str) -> Result<bool, Error>;
+    async fn count_mirrors(&self, peer: &str) -> Result<u64, Error>;
+    async fn peer_state(&self, peer: &str) -> Result<Option<PeerState>, Error>;
+    async fn save_peer_state(&self, state: &PeerState) -> Result<(), Error>;
+}
+
+#[async_trait]
+pub(crate) trait HealthStore: Send + Sync {
+    async fn find_health(&self, name: &str, tld: &str) -> Result<Option<DomainHealth>, Error>;
+    async fn save_health(&self, health: &DomainHealth) -> Result<(), Error>;
+    async fn delete_health(&self, name: &str, tld: &str) -> Result<bool, Error>;
+    async fn healths(&self, alive: Option<bool>, skip: u64, limit: u64) -> Result<Vec<DomainHealth>, Error>;
+}
+
+impl Store {
+    pub(crate) async fn persist(&self, actor: &str, action: &str, before: Option<&Domain>, after: Option<&Domain>) -> Result<bool, Error> {
+        persist(self.domains.as_ref(), self.audit.as_ref(), &self.outbox, actor, action, before, after).await
+    }
+}
+
+pub(crate) async fn persist(db: &dyn DomainStore, log: &dyn AuditStore, outbox: &Outbox, actor: &str, action: &str, before: Option<&Domain>, after: Option<&Domain>) -> Result<bool, Error> {
+    // the audit entry goes first, so a change is never made without one
+    if before.is_some() || after.is_some() {
+        audit::record(log, AuditEntry::new(actor, action, before, after)).await?;
+    }
+
+    let changed = match (before, after) {
+        (None, Some(domain)) => db.insert(domain).await.map(|_| true)?,
+        (Some(before), Some(domain)) => db.update(domain, before.updated_at).await?,
+        (Some(domain), None) => db.delete(&domain.name, &domain.tld).await?,
+        (None, None) => false,
+    };
+
+    if changed {
+        outbox.notify(action, before, after).await;
+    }
+
+    Ok(changed)
+}
+
+/// Hashes domain keys stored in plaintext by earlier versions, returning how many were changed.
+pub(crate) async fn migrate_keys(db: &dyn DomainStore) -> Result<usize, Error> {
+    let (mut skip, mut count) = (0, 0);
+
+    loop {
+        let page = db.list(skip, 100).await?;
+        if page.is_empty() {
+            break Ok(count);
+        }
+        skip += page.len() as u64;
+
+        for mut domain in page {
+            match domain.secret_key.clone() {
+                Some(key) if !secret::is_hashed(&key) => {
+                    domain.set_key(&key);
+                    db.update(&domain, domain.updated_at).await?;
+                    count += 1;
+                }
+                _ => {}
+            }
+        }
+    }
+}
@@ -0,0 +1,507 @@
+use super::{AuditStore, ChangeStore, DeliveryStore, DomainStore, HealthStore, MirrorStore, Stale, TldStore};
+use crate::{
+    audit::AuditEntry,
+    changes::Change,
+    health::DomainHealth,
+    http::Domain,
+    registry::Tld,
+    replication::{Mirror, PeerState},
+    webhook::{Delivery, Status},
+};
+use anyhow::{anyhow, Error};
+use async_trait::async_trait;
+use sled::{
+    transaction::{ConflictableTransactionError, TransactionError},
+    Db, Transactional, Tree,
+};
+use std::sync::Mutex;
+
+pub(crate) struct Embedded {
+    db: Db,
+    domains: Tree,
+    keys: Tree,
+    audit: Tree,
+    tlds: Tree,
+    deliveries: Tree,
+    changes: Tree,
+    mirrors: Tree,
+    peers: Tree,
+    health: Tree,
+    // held while a change takes its seq and is inserted, so changes land in seq order
+    append: Mutex<()>,
+}
+
+impl Embedded {
+    /// Only one process can have the store open at a time, so while the server runs, commands that need the store fail.
+    pub(crate) fn open(path: &String) -> Result<Self, Error> {
+        let db = sled::open(path).map_err(|err| match err {
+            // sled reports the held file lock only through the message
+            sled::Error::Io(io) if io.to_string().contains("could not acquire lock") => {
+                anyhow!("The embedded store at {path} is in use by another process, most likely a running server. Stop it and try again.")
+            }
+            err => err.into(),
+        })?;
+
+        Ok(Self {
+            domains: db.open_tree("domains")?,
+            keys: db.open_tree("key_ids")?,
+            audit: db.open_tree("audit")?,
+            tlds: db.open_tree("tlds")?,
+            deliveries: db.open_tree("deliveries")?,
+            changes: db.open_tree("changes")?,
+            mirrors: db.open_tree("mirrors")?,
+            peers: db.open_tree("peers")?,
+            health: db.open_tree("health")?,
+            append: Mutex::new(()),
+            db,
+        })
+    }
+
+    fn id(name: &str, tld: &str) -> String { format!("{name}.{tld}") }
+
+    fn get(&self, id: &[u8]) -> Result<Option<Domain>, Error> {
+        match self.domains.get(id)? {
+            Some(value) => Ok(Some(serde_json::from_slice(&value)?)),
+            None => Ok(None),
+        }
+    }
+}
+
+#[async_trait]
+impl DomainStore for Embedded {
+    async fn find(&self, name: &str, tld: &str) -> Result<Option<Domain>, Error> { self.get(Self::id(name, tld).as_bytes()) }
+
+    async fn find_by_key(&self, key_id: &str) -> Result<Option<Domain>, Error> {
+        match self.keys.get(key_id)? {
+            Some(id) => self.get(&id),
+            None => Ok(None),
+        }
+    }
+
+    async fn insert(&self, domain: &Domain) -> Result<(), Error> {
+        let id = Self::id(&domain.name, &domain.tld);
+        let value = serde_json::to_vec(domain)?;
+
+        (&self.domains, &self.keys)
+            .transaction(|(domains, keys)| {
+                if domains.get(id.as_bytes())?.is_some() {
+                    return Err(ConflictableTransactionError::Abort(()));
+                }
+                domains.insert(id.as_bytes(), value.as_slice())?;
+                if let Some(key_id) = &domain.key_id {
+                    keys.insert(key_id.as_bytes(), id.as_bytes())?;
+                }
+                Ok(())
+            })
+            .map_err(|_| anyhow!("Domain {id} already exists"))?;
+
+        self.domains.flush_async().await?;
+        Ok(())
+    }
+
+    async fn update(&self, domain: &Domain, expected: Option<i64>) -> Result<bool, Error> {
+        let id = Self::id(&domain.name, &domain.tld);
+        let value = serde_json::to_vec(domain)?;
+
+        // the compare happens inside the transaction, so nothing can land between the check and the write
+        let result = (&self.domains, &self.keys).transaction(|(domains, keys)| {
+            let Some(existing) = domains.get(id.as_bytes())? else { return Ok(false) };
+            let existing: Domain = serde_json::from_slice(&existing).map_err(|_| ConflictableTransactionError::Abort(None))?;
+
+            if existing.updated_at != expected {
+                return Err(ConflictableTransactionError::Abort(Some(Stale)));
+            }
+
+            domains.insert(id.as_bytes(), value.as_slice())?;
+            if existing.key_id != domain.key_id {
+                if let Some(old_id) = &existing.key_id {
+                    keys.remove(old_id.as_bytes())?;
+                }
+                if let Some(key_id) = &domain.key_id {
+                    keys.insert(key_id.as_bytes(), id.as_bytes())?;
+                }
+            }
+            Ok(true)
+        });
+
+        let updated = match result {
+            Ok(updated) => updated,
+            Err(TransactionError::Abort(Some(stale))) => return Err(stale.into()),
+            Err(err) => return Err(anyhow!("Failed to update domain: {err:?}")),
+        };
+
+        self.domains.flush_async().await?;
+        Ok(updated)
+    }
+
+    async fn delete(&self, name: &str, tld: &str) -> Result<bool, Error> {
+        let id = Self::id(name, tld);
+        let Some(existing) = self.get(id.as_bytes())? else { return Ok(false) };
+
+        (&self.domains, &self.keys)
+            .transaction(|(domains, keys)| {
+                domains.remove(id.as_bytes())?;
+                if let Some(key_id) = &existing.key_id {
+                    keys.remove(key_id.as_bytes())?;
+                }
+                Ok::<(), ConflictableTransactionError<()>>(())
+            })
+            .map_err(|err| anyhow!("Failed to delete domain: {err:?}"))?;
+
+        self.domains.flush_async().await?;
+        Ok(true)
+    }
+
+    async fn list(&self, skip: u64, limit: u64) -> Result<Vec<Domain>, Error> {
+        self.domains
+            .iter()
+            .values()
+            .skip(skip as usize)
+            .take(limit as usize)
+            .map(|value| Ok(serde_json::from_slice(&value?)?))
+            .collect()
+    }
+
+    async fn list_public(&self, skip: u64, limit: u64) -> Result<Vec<Domain>, Error> {
+        let mut domains = vec![];
+
+        for value in self.domains.iter().values() {
+            let domain: Domain = serde_json::from_slice(&value?)?;
+            if domain.banned.is_none() {
+                domains.push(domain);
+            }
+            if domains.len() as u64 >= skip + limit {
+                break;
+            }
+        }
+
+        Ok(domains.into_iter().skip(skip as usize).collect())
+    }
+
+    async fn search(&self, query: &str, tld: Option<&str>, skip: u64, limit: u64) -> Result<Vec<Domain>, Error> {
+        let query = query.to_lowercase();
+        let mut domains = vec![];
+
+        for value in self.domains.iter().values() {
+            let domain: Domain = serde_json::from_slice(&value?)?;
+            if tld.is_none_or(|tld| domain.tld == tld) && (domain.name.contains(&query) || domain.ip.to_lowercase().contains(&query)) {
+                domains.push(domain);
+            }
+        }
+
+        Ok(domains.into_iter().skip(skip as usize).take(limit as usize).collect())
+    }
+}
+
+#[async_trait]
+impl TldStore for Embedded {
+    async fn tlds(&self) -> Result<Vec<Tld>, Error> { self.tlds.iter().values().map(|value| Ok(serde_json::from_slice(&value?)?)).collect() }
+
+    async fn find_tld(&self, name: &str) -> Result<Option<Tld>, Error> {
+        match self.tlds.get(name)? {
+            Some(value) => Ok(Some(serde_json::from_slice(&value)?)),
+            None => Ok(None),
+        }
+    }
+
+    async fn save_tld(&self, tld: &Tld) -> Result<(), Error> {
+        self.tlds.insert(&tld.name, serde_json::to_vec(tld)?)?;
+        self.tlds.flush_async().await?;
+        Ok(())
+    }
+
+    async fn delete_tld(&self, name: &str) -> Result<bool, Error> {
+        let removed = self.tlds.remove(name)?.is_some();
+        self.tlds.flush_async().await?;
+        Ok(removed)
+    }
+
+    async fn mark_tlds_imported(&self) -> Result<bool, Error> {
+        let marked = self.db.compare_and_swap("tlds_imported", None as Option<&[u8]>, Some(&[1u8][..]))?.is_ok();
+        self.db.flush_async().await?;
+        Ok(marked)
+    }
+}
+
+#[async_trait]
+impl AuditStore for Embedded {
+    async fn append(&self, entry: &AuditEntry) -> Result<(), Error> {
+        let id = self.db.generate_id()?;
+        self.audit.insert(id.to_be_bytes(), serde_json::to_vec(entry)?)?;
+        self.audit.flush_async().await?;
+        Ok(())
+    }
+
+    async fn query(&self, domain: Option<&str>, skip: u64, limit: u64) -> Result<Vec<AuditEntry>, Error> {
+        let mut entries = vec![];
+
+        for value in self.audit.iter().values().rev() {
+            let entry: AuditEntry = serde_json::from_slice(&value?)?;
+            if domain.is_none_or(|domain| entry.domain == domain) {
+                entries.push(entry);
+            }
+            if entries.len() as u64 >= skip + limit {
+                break;
+            }
+        }
+
+        Ok(entries.into_iter().skip(skip as usize).collect())
+    }
+}
+
+#[async_trait]
+impl DeliveryStore for Embedded {
+    async fn save_delivery(&self, delivery: &Delivery) -> Result<(), Error> {
+        self.deliveries.insert(&delivery.id, serde_json::to_vec(delivery)?)?;
+        self.deliveries.flush_async().await?;
+        Ok(())
+    }
+
+    async fn find_delivery(&self, id: &str) -> Result<Option<Delivery>, Error> {
+        match self.deliveries.get(id)? {
+            Some(value) => Ok(Some(serde_json::from_slice(&value)?)),
+            None => Ok(None),
+        }
+    }
+
+    async fn due_deliveries(&self, now: i64, limit: u64) -> Result<Vec<Delivery>, Error> {
+        let mut due = vec![];
+
+        for value in self.deliveries.iter().values() {
+            let delivery: Delivery = serde_json::from_slice(&value?)?;
+            if delivery.status == Status::Pending && delivery.next_at <= now {
+                due.push(delivery);
+            }
+            if due.len() as u64 >= limit {
+                break;
+            }
+        }
+
+        Ok(due)
+    }
+
+    async fn deliveries(&self, status: Option<Status>, skip: u64, limit: u64) -> Result<Vec<Delivery>, Error> {
+        let mut deliveries = vec![];
+
+        for value in self.deliveries.iter().values().rev() {
+            let delivery: Delivery = serde_json::from_slice(&value?)?;
+            if status.is_none_or(|status| delivery.status == status) {
+                deliveries.push(delivery);
+            }
+            if deliveries.len() as u64 >= skip + limit {
+                break;
+            }
+        }
+
+        Ok(deliveries.into_iter().skip(skip as usize).collect())
+    }
+}
+
+#[async_trait]
+impl ChangeStore for Embedded {
+    async fn append_change(&self, mut change: Change) -> Result<u64, Error> {
+        {
+            let _append = self.append.lock().map_err(|_| anyhow!("change feed lock poisoned"))?;
+            change.seq = self.db.generate_id()? + 1;
+            self.changes.insert(change.seq.to_be_bytes(), serde_json::to_vec(&change)?)?;
+        }
+        self.changes.flush_async().await?;
+        Ok(change.seq)
+    }
+
+    async fn changes_since(&self, seq: u64, limit: u64) -> Result<Vec<Change>, Error> {
+        self.changes
+            .range((seq + 1).to_be_bytes()..)
+            .values()
+            .take(limit as usize)
+            .map(|value| Ok(serde_json::from_slice(&value?)?))
+            .collect()
+    }
+
+    async fn last_seq(&self) -> Result<u64, Error> {
+        match self.changes.last()? {
+            Some((key, _)) => Ok(u64::from_be_bytes(key.as_ref().try_into()?)),
+            None => Ok(0),
+        }
+    }
+
+    async fn prune_changes(&self, keep: u64) -> Result<u64, Error> {
+        let Some(cutoff) = self.last_seq().await?.checked_sub(keep) else { return Ok(0) };
+        let mut pruned = 0;
+
+        for key in self.changes.range(..=cutoff.to_be_bytes()).keys() {
+            self.changes.remove(key?)?;
+            pruned += 1;
+        }
+        self.changes.flush_async().await?;
+        Ok(pruned)
+    }
+}
+
+// mirrors are keyed `name.tld/peer` so every copy of a name shares a prefix
+#[async_trait]
+impl MirrorStore for Embedded {
+    async fn find_mirrors(&self, name: &str, tld: &str) -> Result<Vec<Mirror>, Error> {
+        self.mirrors
+            .scan_prefix(format!("{}/", Self::id(name, tld)))
+            .values()
+            .map(|value| Ok(serde_json::from_slice(&value?)?))
+            .collect()
+    }
+
+    async fn save_mirror(&self, mirror: &Mirror) -> Result<(), Error> {
+        let id = format!("{}/{}", Self::id(&mirror.domain.name, &mirror.domain.tld), mirror.peer);
+        let first_seen = match self.mirrors.get(&id)? {
+            Some(value) => serde_json::from_slice::<Mirror>(&value)?.first_seen.into_iter().chain(mirror.first_seen).min(),
+            None => mirror.first_seen,
+        };
+
+        self.mirrors.insert(id, serde_json::to_vec(&Mirror { first_seen, ..mirror.clone() })?)?;
+        self.mirrors.flush_async().await?;
+        Ok(())
+    }
+
+    async fn delete_mirror(&self, peer: &str, name: &str, tld: &str) -> Result<bool, Error> {
+        let removed = self.mirrors.remove(format!("{}/{peer}", Self::id(name, tld)))?.is_some();
+        self.mirrors.flush_async().await?;
+        Ok(removed)
+    }
+
+    async fn count_mirrors(&self, peer: &str) -> Result<u64, Error> {
+        let suffix = format!("/{peer}");
+        let mut count = 0;
+
+        for key in self.mirrors.iter().keys() {
+            if key?.ends_with(suffix.as_bytes()) {
+                count += 1;
+            }
+        }
+
+        Ok(count)
+    }
+
+    async fn peer_state(&self, peer: &str) -> Result<Option<PeerState>, Error> {
+        match self.peers.get(peer)? {
+            Some(value) => Ok(Some(serde_json::from_slice(&value)?)),
+            None => Ok(None),
+        }
+    }
+
+    async fn save_peer_state(&self, state: &PeerState) -> Result<(), Error> {
+        self.peers.insert(&state.peer, serde_json::to_vec(state)?)?;
+        self.peers.flush_async().await?;
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl HealthStore for Embedded {
+    async fn find_health(&self, name: &str, tld: &str) -> Result<Option<DomainHealth>, Error> {
+        match self.health.get(Self::id(name, tld))? {
+            Some(value) => Ok(Some(serde_json::from_slice(&value)?)),
+            None => Ok(None),
+        }
+    }
+
+    async fn save_health(&self, health: &DomainHealth) -> Result<(), Error> {
+        self.health.insert(Self::id(&health.name, &health.tld), serde_json::to_vec(health)?)?;
+        self.health.flush_async().await?;
+        Ok(())
+    }
+
+    async fn delete_health(&self, name: &str, tld: &str) -> Result<bool, Error> {
+        let removed = self.health.remove(Self::id(name, tld))?.is_some();
+        self.health.flush_async().await?;
+        Ok(removed)
+    }
+
+    async fn healths(&self, alive: Option<bool>, skip: u64, limit: u64) -> Result<Vec<DomainHealth>, Error> {
+        let mut healths = vec![];
+
+        for value in self.health.iter().values() {
+            let health: DomainHealth = serde_json::from_slice(&value?)?;
+            if alive.is_none_or(|alive| health.alive == alive) {
+                healths.push(health);
+            }
+        }
+
+        Ok(healths.into_iter().skip(skip as usize).take(limit as usize).collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        http::{Ban, Subdomain},
+        webhook::Event,
+    };
+
+    #[actix_web::test]
+    async fn update_refuses_a_stale_copy() {
+        let path = std::env::temp_dir().join(format!("webx-embedded-stale-{}", std::process::id())).to_string_lossy().into_owned();
+        let store = Embedded::open(&path).unwrap();
+        let domain = Domain { name: "bank".into(), tld: "it".into(), ip: "1.1.1.1".into(), ..Default::default() };
+        store.insert(&domain).await.unwrap();
+
+        let (mut first, mut second) = (domain.clone(), domain.clone());
+        first.ip = "2.2.2.2".into();
+        first.touch();
+        second.ip = "3.3.3.3".into();
+        second.touch();
+
+        let applied = store.update(&first, domain.updated_at).await.unwrap();
+        let stale = store.update(&second, domain.updated_at).await.unwrap_err();
+        let saved = store.find("bank", "it").await.unwrap().unwrap();
+        std::fs::remove_dir_all(&path).unwrap();
+
+        assert!(applied);
+        assert!(stale.is::<Stale>());
+        assert_eq!(saved.ip, "2.2.2.2");
+    }
+
+    #[actix_web::test]
+    async fn pruning_keeps_the_newest_changes() {
+        let path = std::env::temp_dir().join(format!("webx-embedded-prune-{}", std::process::id())).to_string_lossy().into_owned();
+        let store = Embedded::open(&path).unwrap();
+        for _ in 0..5 {
+            let change = Change { seq: 0, event: Event::Update, action: "update_ip".into(), domain: "bank.it".into(), at: 0, after: None };
+            store.append_change(change).await.unwrap();
+        }
+        let last = store.last_seq().await.unwrap();
+
+        let pruned = store.prune_changes(2).await.unwrap();
+        let left: Vec<u64> = store.changes_since(0, 10).await.unwrap().into_iter().map(|change| change.seq).collect();
+        let again = store.prune_changes(2).await.unwrap();
+        let nothing = store.prune_changes(10).await.unwrap();
+        std::fs::remove_dir_all(&path).unwrap();
+
+        assert_eq!(pruned, 3);
+        assert_eq!(left, [last - 1, last]);
+        assert_eq!((again, nothing), (0, 0));
+        assert_eq!(store.last_seq().await.unwrap(), last);
+    }
+
+    #[actix_web::test]
+    async fn subdomains_resolve_through_their_parent() {
+        let path = std::env::temp_dir().join(format!("webx-embedded-subdomain-{}", std::process::id())).to_string_lossy().into_owned();
+        let store = Embedded::open(&path).unwrap();
+        let subdomains = vec![Subdomain { name: "api".into(), ip: "2.2.2.2".into() }];
+        let bank = Domain { name: "bank".into(), tld: "it".into(), ip: "1.1.1.1".into(), subdomains: subdomains.clone(), ..Default::default() };
+        let banned = Domain { name: "shop".into(), banned: Some(Ban { reason: None, at: 0 }), ..bank.clone() };
+        let expired = Domain { name: "old".into(), expires_at: Some(1), ..bank.clone() };
+        for domain in [&bank, &banned, &expired] {
+            store.insert(domain).await.unwrap();
+        }
+
+        let api = store.resolve("api.bank", "it").await.unwrap().unwrap();
+        let missing = store.resolve("www.bank", "it").await.unwrap();
+        let hidden = (store.resolve("api.shop", "it").await.unwrap(), store.resolve("api.old", "it").await.unwrap());
+        std::fs::remove_dir_all(&path).unwrap();
+
+        assert_eq!((api.name.as_str(), api.tld.as_str(), api.ip.as_str()), ("api.bank", "it", "2.2.2.2"));
+        assert!(api.secret_key.is_none());
+        assert!(missing.is_none());
+        assert!(hidden.0.is_none() && hidden.1.is_none());
+    }
+}
@@ -0,0 +1,292 @@
+use super::{AuditStore, ChangeStore, DeliveryStore, DomainStore, HealthStore, MirrorStore, Stale, TldStore};
+use crate::{
+    audit::AuditEntry,
+    changes::{self, Change},
+    health::DomainHealth,
+    http::Domain,
+    registry::Tld,
+    replication::{Mirror, PeerState},
+    webhook::{Delivery, Status},
+};
+use anyhow::Error;
+use async_trait::async_trait;
+use futures::stream::{StreamExt, TryStreamExt};
+use mongodb::{
+    bson::{self, doc, Document},
+    options::{FindOneAndUpdateOptions, IndexOptions, FindOneOptions, FindOptions, ReplaceOptions, ReturnDocument, UpdateOptions},
+    Collection, Cursor, Database, IndexModel,
+};
+
+pub(crate) struct Mongo {
+    collection: Collection<Domain>,
+    audit: Collection<AuditEntry>,
+    tlds: Collection<Tld>,
+    deliveries: Collection<Delivery>,
+    changes: Collection<Change>,
+    counters: Collection<Document>,
+    mirrors: Collection<Mirror>,
+    peers: Collection<PeerState>,
+    health: Collection<DomainHealth>,
+}
+
+impl Mongo {
+    /// Opens the collections and makes sure the indexes lookups rely on exist.
+    pub(crate) async fn open(db: Database) -> Result<Self, Error> {
+        let store = Self::new(db);
+        let unique = || IndexOptions::builder().unique(true);
+
+        store.collection.create_index(IndexModel::builder().keys(doc! { "name": 1, "tld": 1 }).options(unique().build()).build(), None).await?;
+        // domains without a key yet are left out, so they do not collide on a missing `key_id`
+        let key_id = unique().partial_filter_expression(doc! { "key_id": { "$type": "string" } }).build();
+        store.collection.create_index(IndexModel::builder().keys(doc! { "key_id": 1 }).options(key_id).build(), None).await?;
+        store.changes.create_index(IndexModel::builder().keys(doc! { "seq": 1 }).options(unique().build()).build(), None).await?;
+
+        Ok(store)
+    }
+
+    fn new(db: Database) -> Self {
+        Self {
+            collection: db.collection::<Domain>("domains"),
+            audit: db.collection::<AuditEntry>("audit"),
+            tlds: db.collection::<Tld>("tlds"),
+            deliveries: db.collection::<Delivery>("deliveries"),
+            changes: db.collection::<Change>("changes"),
+            counters: db.collection::<Document>("counters"),
+            mirrors: db.collection::<Mirror>("mirrors"),
+            peers: db.collection::<PeerState>("peers"),
+            health: db.collection::<DomainHealth>("health"),
+        }
+    }
+}
+
+// a document that no longer decodes is logged and left out rather than failing the whole page
+async fn decodable(cursor: Cursor<Domain>) -> Vec<Domain> {
+    cursor
+        .filter_map(|result| async {
+            match result {
+                Ok(domain) => Some(domain),
+                Err(err) => {
+                    log::warn!("Skipping domain that failed to decode: {err}");
+                    None
+                }
+            }
+        })
+        .collect()
+        .await
+}
+
+#[async_trait]
+impl DomainStore for Mongo {
+    async fn find(&self, name: &str, tld: &str) -> Result<Option<Domain>, Error> { Ok(self.collection.find_one(doc! { "name": name, "tld": tld }, None).await?) }
+
+    async fn find_by_key(&self, key_id: &str) -> Result<Option<Domain>, Error> { Ok(self.collection.find_one(doc! { "key_id": key_id }, None).await?) }
+
+    async fn insert(&self, domain: &Domain) -> Result<(), Error> {
+        self.collection.insert_one(domain, None).await?;
+        Ok(())
+    }
+
+    async fn update(&self, domain: &Domain, expected: Option<i64>) -> Result<bool, Error> {
+        let filter = doc! { "name": &domain.name, "tld": &domain.tld, "updated_at": expected };
+        let result = self.collection.replace_one(filter, domain, None).await?;
+
+        match result.matched_count {
+            1 => Ok(true),
+            _ if self.find(&domain.name, &domain.tld).await?.is_some() => Err(Stale.into()),
+            _ => Ok(false),
+        }
+    }
+
+    async fn delete(&self, name: &str, tld: &str) -> Result<bool, Error> {
+        let result = self.collection.delete_one(doc! { "name": name, "tld": tld }, None).await?;
+        Ok(result.deleted_count == 1)
+    }
+
+    async fn list(&self, skip: u64, limit: u64) -> Result<Vec<Domain>, Error> {
+        let find_options = FindOptions::builder().skip(Some(skip)).limit(Some(limit as i64)).build();
+        let cursor = self.collection.find(None, find_options).await?;
+
+        Ok(decodable(cursor).await)
+    }
+
+    async fn list_public(&self, skip: u64, limit: u64) -> Result<Vec<Domain>, Error> {
+        let find_options = FindOptions::builder().skip(Some(skip)).limit(Some(limit as i64)).build();
+        let cursor = self.collection.find(doc! { "banned": null }, find_options).await?;
+
+        Ok(decodable(cursor).await)
+    }
+
+    async fn search(&self, query: &str, tld: Option<&str>, skip: u64, limit: u64) -> Result<Vec<Domain>, Error> {
+        let pattern = doc! { "$regex": regex::escape(query), "$options": "i" };
+        let mut filter = doc! { "$or": [{ "name": pattern.clone() }, { "ip": pattern }] };
+
+        if let Some(tld) = tld {
+            filter.insert("tld", tld);
+        }
+
+        let find_options = FindOptions::builder().skip(Some(skip)).limit(Some(limit as i64)).build();
+        let cursor = self.collection.find(filter, find_options).await?;
+
+        Ok(decodable(cursor).await)
+    }
+}
+
+#[async_trait]
+impl TldStore for Mongo {
+    async fn tlds(&self) -> Result<Vec<Tld>, Error> {
+        let find_options = FindOptions::builder().sort(doc! { "name": 1 }).build();
+        let cursor = self.tlds.find(None, find_options).await?;
+
+        Ok(cursor.try_collect().await?)
+    }
+
+    async fn find_tld(&self, name: &str) -> Result<Option<Tld>, Error> { Ok(self.tlds.find_one(doc! { "name": name }, None).await?) }
+
+    async fn save_tld(&self, tld: &Tld) -> Result<(), Error> {
+        let options = ReplaceOptions::builder().upsert(true).build();
+        self.tlds.replace_one(doc! { "name": &tld.name }, tld, options).await?;
+        Ok(())
+    }
+
+    async fn delete_tld(&self, name: &str) -> Result<bool, Error> {
+        let result = self.tlds.delete_one(doc! { "name": name }, None).await?;
+        Ok(result.deleted_count == 1)
+    }
+
+    async fn mark_tlds_imported(&self) -> Result<bool, Error> {
+        let options = UpdateOptions::builder().upsert(true).build();
+        let result = self.counters.update_one(doc! { "_id": "tlds_imported" }, doc! { "$setOnInsert": { "at": chrono::Utc::now().timestamp() } }, options).await?;
+        Ok(result.upserted_id.is_some())
+    }
+}
+
+#[async_trait]
+impl AuditStore for Mongo {
+    async fn append(&self, entry: &AuditEntry) -> Result<(), Error> {
+        self.audit.insert_one(entry, None).await?;
+        Ok(())
+    }
+
+    async fn query(&self, domain: Option<&str>, skip: u64, limit: u64) -> Result<Vec<AuditEntry>, Error> {
+        let filter = domain.map(|domain| doc! { "domain": domain });
+        let find_options = FindOptions::builder().sort(doc! { "_id": -1 }).skip(Some(skip)).limit(Some(limit as i64)).build();
+        let cursor = self.audit.find(filter, find_options).await?;
+
+        Ok(cursor.try_collect().await?)
+    }
+}
+
+#[async_trait]
+impl DeliveryStore for Mongo {
+    async fn save_delivery(&self, delivery: &Delivery) -> Result<(), Error> {
+        let options = ReplaceOptions::builder().upsert(true).build();
+        self.deliveries.replace_one(doc! { "id": &delivery.id }, delivery, options).await?;
+        Ok(())
+    }
+
+    async fn find_delivery(&self, id: &str) -> Result<Option<Delivery>, Error> { Ok(self.deliveries.find_one(doc! { "id": id }, None).await?) }
+
+    async fn due_deliveries(&self, now: i64, limit: u64) -> Result<Vec<Delivery>, Error> {
+        let filter = doc! { "status": Status::Pending.to_string(), "next_at": { "$lte": now } };
+        let find_options = FindOptions::builder().sort(doc! { "next_at": 1 }).limit(Some(limit as i64)).build();
+        let cursor = self.deliveries.find(filter, find_options).await?;
+
+        Ok(cursor.try_collect().await?)
+    }
+
+    async fn deliveries(&self, status: Option<Status>, skip: u64, limit: u64) -> Result<Vec<Delivery>, Error> {
+        let filter = status.map(|status| doc! { "status": status.to_string() });
+        let find_options = FindOptions::builder().sort(doc! { "id": -1 }).skip(Some(skip)).limit(Some(limit as i64)).build();
+        let cursor = self.deliveries.find(filter, find_options).await?;
+
+        Ok(cursor.try_collect().await?)
+    }
+}
+
+#[async_trait]
+impl ChangeStore for Mongo {
+    async fn append_change(&self, mut change: Change) -> Result<u64, Error> {
+        let options = FindOneAndUpdateOptions::builder().upsert(true).return_document(ReturnDocument::After).build();
+        let counter = self.counters.find_one_and_update(doc! { "_id": "changes" }, doc! { "$inc": { "seq": 1_i64 } }, options).await?;
+
+        change.seq = counter.and_then(|counter| counter.get_i64("seq").ok()).unwrap_or_default() as u64;
+        self.changes.insert_one(&change, None).await?;
+        Ok(change.seq)
+    }
+
+    async fn changes_since(&self, seq: u64, limit: u64) -> Result<Vec<Change>, Error> {
+        let find_options = FindOptions::builder().sort(doc! { "seq": 1 }).limit(Some(limit as i64)).build();
+        let cursor = self.changes.find(doc! { "seq": { "$gt": seq as i64 } }, find_options).await?;
+
+        Ok(changes::contiguous(seq, cursor.try_collect().await?, chrono::Utc::now().timestamp()))
+    }
+
+    async fn last_seq(&self) -> Result<u64, Error> {
+        let find_options = FindOneOptions::builder().sort(doc! { "seq": -1 }).build();
+        Ok(self.changes.find_one(None, find_options).await?.map_or(0, |change| change.seq))
+    }
+
+    async fn prune_changes(&self, keep: u64) -> Result<u64, Error> {
+        let Some(cutoff) = self.last_seq().await?.checked_sub(keep) else { return Ok(0) };
+        Ok(self.changes.delete_many(doc! { "seq": { "$lte": cutoff as i64 } }, None).await?.deleted_count)
+    }
+}
+
+#[async_trait]
+impl MirrorStore for Mongo {
+    async fn find_mirrors(&self, name: &str, tld: &str) -> Result<Vec<Mirror>, Error> {
+        let cursor = self.mirrors.find(doc! { "name": name, "tld": tld }, None).await?;
+        Ok(cursor.try_collect().await?)
+    }
+
+    async fn save_mirror(&self, mirror: &Mirror) -> Result<(), Error> {
+        let options = UpdateOptions::builder().upsert(true).build();
+        let filter = doc! { "peer": &mirror.peer, "name": &mirror.domain.name, "tld": &mirror.domain.tld };
+        let mut fields = bson::to_document(mirror)?;
+        fields.remove("first_seen");
+
+        // `$min` keeps the first time the copy was seen across syncs
+        let update = doc! { "$set": fields, "$min": { "first_seen": mirror.first_seen.unwrap_or_else(|| chrono::Utc::now().timestamp()) } };
+        self.mirrors.update_one(filter, update, options).await?;
+        Ok(())
+    }
+
+    async fn delete_mirror(&self, peer: &str, name: &str, tld: &str) -> Result<bool, Error> {
+        let result = self.mirrors.delete_one(doc! { "peer": peer, "name": name, "tld": tld }, None).await?;
+        Ok(result.deleted_count == 1)
+    }
+
+    async fn count_mirrors(&self, peer: &str) -> Result<u64, Error> { Ok(self.mirrors.count_documents(doc! { "peer": peer }, None).await?) }
+
+    async fn peer_state(&self, peer: &str) -> Result<Option<PeerState>, Error> { Ok(self.peers.find_one(doc! { "peer": peer }, None).await?) }
+
+    async fn save_peer_state(&self, state: &PeerState) -> Result<(), Error> {
+        let options = ReplaceOptions::builder().upsert(true).build();
+        self.peers.replace_one(doc! { "peer": &state.peer }, state, options).await?;
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl HealthStore for Mongo {
+    async fn find_health(&self, name: &str, tld: &str) -> Result<Option<DomainHealth>, Error> { Ok(self.health.find_one(doc! { "name": name, "tld": tld }, None).await?) }
+
+    async fn save_health(&self, health: &DomainHealth) -> Result<(), Error> {
+        let options = ReplaceOptions::builder().upsert(true).build();
+        self.health.replace_one(doc! { "name": &health.name, "tld": &health.tld }, health, options).await?;
+        Ok(())
+    }
+
+    async fn delete_health(&self, name: &str, tld: &str) -> Result<bool, Error> {
+        let result = self.health.delete_one(doc! { "name": name, "tld": tld }, None).await?;
+        Ok(result.deleted_count == 1)
+    }
+
+    async fn healths(&self, alive: Option<bool>, skip: u64, limit: u64) -> Result<Vec<DomainHealth>, Error> {
+        let filter = alive.map(|alive| doc! { "alive": alive });
+        let find_options = FindOptions::builder().sort(doc! { "tld": 1, "name": 1 }).skip(Some(skip)).limit(Some(limit as i64)).build();
+        let cursor = self.health.find(filter, find_options).await?;
+
+        Ok(cursor.try_collect().await?)
+    }
+}
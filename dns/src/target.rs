@@ -0,0 +1,118 @@
+use anyhow::{anyhow, Error};
+use hyper::client::connect::dns::Name;
+use reqwest::{
+    dns::{Addrs, Resolve, Resolving},
+    redirect, Url,
+};
+use std::{
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    sync::Arc,
+    time::Duration,
+};
+
+const MAX_BODY: usize = 64 * 1024;
+
+pub(crate) fn client() -> Result<reqwest::Client, Error> {
+    let policy = redirect::Policy::custom(|attempt| match attempt.previous().len() < 3 && is_public(attempt.url()) {
+        true => attempt.follow(),
+        false => attempt.stop(),
+    });
+
+    Ok(reqwest::Client::builder().timeout(Duration::from_secs(10)).redirect(policy).no_proxy().dns_resolver(Arc::new(PublicResolver)).build()?)
+}
+
+/// Resolves host names for target fetches and refuses names with any private or loopback address, so the checked
+/// addresses are the ones connected to.
+struct PublicResolver;
+
+impl Resolve for PublicResolver {
+    fn resolve(&self, name: Name) -> Resolving {
+        Box::pin(async move {
+            let addrs: Vec<SocketAddr> = tokio::net::lookup_host((name.as_str(), 0)).await?.collect();
+
+            if addrs.iter().any(|addr| !is_public_ip(addr.ip())) {
+                return Err(anyhow!("{} resolves to a private address", name.as_str()).into());
+            }
+
+            Ok(Box::new(addrs.into_iter()) as Addrs)
+        })
+    }
+}
+
+/// Where `path` is served for a domain target, following Napture: the web root of an IP or URL, or a branch of a GitHub repo.
+pub(crate) fn url(target: &str, path: &str) -> String {
+    let path = path.trim_start_matches('/');
+
+    match target.parse::<IpAddr>() {
+        Ok(IpAddr::V4(ip)) => return format!("http://{ip}/{path}"),
+        Ok(IpAddr::V6(ip)) => return format!("http://[{ip}]/{path}"),
+        Err(_) => {}
+    }
+
+    if let Some(repo) = target.strip_prefix("https://github.com/") {
+        let parts: Vec<&str> = repo.trim_end_matches('/').split('/').collect();
+        let (branch, dir) = match parts.get(2) {
+            Some(&"tree") => (parts.get(3).copied().unwrap_or("main"), parts.iter().skip(4).map(|part| format!("{part}/")).collect()),
+            _ => ("main", String::new()),
+        };
+
+        return format!("https://raw.githubusercontent.com/{}/{}/{branch}/{dir}{path}", parts[0], parts.get(1).copied().unwrap_or(""));
+    }
+
+    format!("{}/{path}", target.trim_end_matches('/'))
+}
+
+/// Fetches `path` from a domain target, reading at most 64 KiB of the body. Private and loopback addresses are refused.
+pub(crate) async fn fetch(client: &reqwest::Client, target: &str, path: &str) -> Result<String, Error> {
+    let url = Url::parse(&url(target, path))?;
+
+    if !is_public(&url) {
+        return Err(anyhow!("{url} is not a public address"));
+    }
+
+    let mut response = client.get(url).send().await?.error_for_status()?;
+    let mut body = Vec::new();
+
+    while let Some(chunk) = response.chunk().await? {
+        body.extend_from_slice(&chunk);
+        if body.len() >= MAX_BODY {
+            body.truncate(MAX_BODY);
+            break;
+        }
+    }
+
+    Ok(String::from_utf8_lossy(&body).into_owned())
+}
+
+// Host names are checked again by `PublicResolver` when they are resolved.
+fn is_public(url: &Url) -> bool {
+    match url.host() {
+        Some(url::Host::Ipv4(ip)) => is_public_v4(ip),
+        Some(url::Host::Ipv6(ip)) => is_public_v6(ip),
+        Some(url::Host::Domain(host)) => !host.eq_ignore_ascii_case("localhost") && !host.to_ascii_lowercase().ends_with(".localhost"),
+        None => false,
+    }
+}
+
+fn is_public_ip(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => is_public_v4(ip),
+        IpAddr::V6(ip) => is_public_v6(ip),
+    }
+}
+
+fn is_public_v4(ip: Ipv4Addr) -> bool {
+    let [a, b, ..] = ip.octets();
+
+    // 0.0.0.0/8 and the 100.64.0.0/10 carrier-grade NAT range are not reachable from outside either
+    !(ip.is_private() || ip.is_loopback() || ip.is_link_local() || ip.is_broadcast() || ip.is_documentation() || a == 0 || (a == 100 && (b & 0xc0) == 64))
+}
+
+fn is_public_v6(ip: Ipv6Addr) -> bool {
+    if let Some(ip) = ip.to_ipv4_mapped() {
+        return is_public_v4(ip);
+    }
+
+    // fc00::/7 is unique local and fe80::/10 link local
+    !(ip.is_loopback() || ip.is_unspecified() || (ip.segments()[0] & 0xfe00) == 0xfc00 || (ip.segments()[0] & 0xffc0) == 0xfe80)
+}
@@ -0,0 +1,43 @@
+use crate::{http::Domain, secret, target};
+use anyhow::{anyhow, Error};
+use serde::{Deserialize, Serialize};
+
+pub(crate) const PATH: &str = ".well-known/webx-verify";
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub(crate) struct Verification {
+    pub(crate) token: String,
+    pub(crate) issued_at: i64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) target: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) verified_at: Option<i64>,
+}
+
+impl Verification {
+    pub(crate) fn issue() -> Self {
+        Verification {
+            token: format!("webx-verify={}", secret::generate(32)),
+            issued_at: chrono::Utc::now().timestamp(),
+            target: None,
+            verified_at: None,
+        }
+    }
+
+    /// A verification only holds for the target it was made against, so changing the target drops it.
+    pub(crate) fn holds_for(&self, target: &str) -> bool { self.verified_at.is_some() && self.target.as_deref() == Some(target) }
+}
+
+/// Fetches the verification file from the domain's target and looks for the token on a line of its own.
+pub(crate) async fn check(client: &reqwest::Client, domain: &Domain) -> Result<(), Error> {
+    let Some(verification) = &domain.verification else {
+        return Err(anyhow!("No verification token has been issued for this domain."));
+    };
+
+    let body = target::fetch(client, &domain.ip, PATH).await.map_err(|err| anyhow!("Failed to fetch {}: {err}", target::url(&domain.ip, PATH)))?;
+
+    match body.lines().any(|line| line.trim() == verification.token) {
+        true => Ok(()),
+        false => Err(anyhow!("{} does not contain the verification token.", target::url(&domain.ip, PATH))),
+    }
+}
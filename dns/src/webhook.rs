@@ -0,0 +1,391 @@
+use crate::{
+    audit,
+    changes::Feed,
+    config::Config,
+    http::Domain,
+    secret,
+    store::{DeliveryStore, Store},
+};
+use anyhow::{anyhow, Error};
+use hmac::{Hmac, Mac};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use sha2::Sha256;
+use std::{fmt, sync::Arc, time::Duration};
+
+const MAX_BACKOFF: i64 = 6 * 3600;
+
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum Event {
+    Create,
+    Update,
+    Delete,
+    Expire,
+}
+
+impl Event {
+    pub(crate) const ALL: [Event; 4] = [Event::Create, Event::Update, Event::Delete, Event::Expire];
+
+    pub(crate) fn from_change(action: &str, before: Option<&Domain>, after: Option<&Domain>) -> Option<Event> {
+        match (before, after) {
+            (None, Some(_)) => Some(Event::Create),
+            (Some(_), Some(_)) => Some(Event::Update),
+            (Some(_), None) if matches!(action, "expire" | "reclaim") => Some(Event::Expire),
+            (Some(_), None) => Some(Event::Delete),
+            (None, None) => None,
+        }
+    }
+}
+
+impl fmt::Display for Event {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Event::Create => "create",
+            Event::Update => "update",
+            Event::Delete => "delete",
+            Event::Expire => "expire",
+        })
+    }
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, PartialEq, Eq, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum Status {
+    Pending,
+    Delivered,
+    Failed,
+}
+
+impl fmt::Display for Status {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Status::Pending => "pending",
+            Status::Delivered => "delivered",
+            Status::Failed => "failed",
+        })
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub(crate) struct Endpoint {
+    pub(crate) url: String,
+    pub(crate) secret: String,
+    #[serde(default = "all_events")]
+    pub(crate) events: Vec<Event>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Webhooks {
+    #[serde(default)]
+    pub(crate) endpoints: Vec<Endpoint>,
+    #[serde(default = "default_max_attempts")]
+    pub(crate) max_attempts: u32,
+    #[serde(default = "default_interval")]
+    pub(crate) interval: u64,
+}
+
+impl Default for Webhooks {
+    fn default() -> Self {
+        Self {
+            endpoints: vec![],
+            max_attempts: default_max_attempts(),
+            interval: default_interval(),
+        }
+    }
+}
+
+fn all_events() -> Vec<Event> { Event::ALL.to_vec() }
+fn default_max_attempts() -> u32 { 8 }
+fn default_interval() -> u64 { 10 }
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub(crate) struct Delivery {
+    pub(crate) id: String,
+    pub(crate) url: String,
+    pub(crate) event: Event,
+    pub(crate) domain: String,
+    pub(crate) payload: String,
+    pub(crate) status: Status,
+    pub(crate) attempts: u32,
+    pub(crate) created_at: i64,
+    pub(crate) next_at: i64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) delivered_at: Option<i64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) last_error: Option<String>,
+}
+
+#[derive(Clone)]
+pub(crate) struct Outbox {
+    pub(crate) queue: Arc<dyn DeliveryStore>,
+    pub(crate) feed: Feed,
+    endpoints: Arc<Vec<Endpoint>>,
+}
+
+impl Outbox {
+    pub(crate) fn new(queue: Arc<dyn DeliveryStore>, feed: Feed, webhooks: &Webhooks) -> Self {
+        Outbox {
+            queue,
+            feed,
+            endpoints: Arc::new(webhooks.endpoints.clone()),
+        }
+    }
+
+    /// Publishes the change to the feed and queues a delivery for every endpoint subscribed to it. Failures are logged, never returned.
+    pub(crate) async fn notify(&self, action: &str, before: Option<&Domain>, after: Option<&Domain>) {
+        let Some(event) = Event::from_change(action, before, after) else { return };
+        self.feed.publish(event, action, before, after).await;
+
+        let now = chrono::Utc::now();
+        let domain = after.or(before).map(|domain| format!("{}.{}", domain.name, domain.tld)).unwrap_or_default();
+
+        let payload = json!({
+            "event": event,
+            "action": action,
+            "domain": domain,
+            "at": now.timestamp(),
+            "before": before.and_then(audit::public),
+            "after": after.and_then(audit::public),
+        })
+        .to_string();
+
+        for endpoint in self.endpoints.iter().filter(|endpoint| endpoint.events.contains(&event)) {
+            let delivery = Delivery {
+                id: format!("{:012x}{}", now.timestamp_millis(), secret::generate(8)),
+                url: endpoint.url.clone(),
+                event,
+                domain: domain.clone(),
+                payload: payload.clone(),
+                status: Status::Pending,
+                attempts: 0,
+                created_at: now.timestamp(),
+                next_at: now.timestamp(),
+                delivered_at: None,
+                last_error: None,
+            };
+
+            if let Err(err) = self.queue.save_delivery(&delivery).await {
+                log::error!("Failed to queue {event} webhook for {domain} to {}: {err}", endpoint.url);
+            }
+        }
+    }
+}
+
+pub(crate) fn spawn(store: Store, config: &Config) {
+    let webhooks = config.webhooks().clone();
+
+    if webhooks.endpoints.is_empty() {
+        return;
+    }
+
+    actix_web::rt::spawn(async move {
+        let client = match reqwest::Client::builder().timeout(Duration::from_secs(10)).build() {
+            Ok(client) => client,
+            Err(err) => return log::error!("Failed to build webhook client: {err}"),
+        };
+        let mut interval = actix_web::rt::time::interval(Duration::from_secs(webhooks.interval.max(1)));
+
+        loop {
+            interval.tick().await;
+            match flush(store.outbox.queue.as_ref(), &client, &webhooks).await {
+                Ok((0, 0)) => {}
+                Ok((delivered, failed)) => log::info!("Webhooks: delivered {delivered}, gave up on {failed}"),
+                Err(err) => log::error!("Webhook delivery failed: {err}"),
+            }
+        }
+    });
+}
+
+pub(crate) async fn flush(queue: &dyn DeliveryStore, client: &reqwest::Client, webhooks: &Webhooks) -> Result<(usize, usize), Error> {
+    let now = chrono::Utc::now().timestamp();
+    let (mut delivered, mut failed) = (0, 0);
+
+    for mut delivery in queue.due_deliveries(now, 100).await? {
+        let result = match webhooks.endpoints.iter().find(|endpoint| endpoint.url == delivery.url) {
+            Some(endpoint) => send(client, endpoint, &delivery).await,
+            None => Err(anyhow!("endpoint is no longer configured")),
+        };
+
+        delivery.attempts += 1;
+
+        match result {
+            Ok(()) => {
+                delivery.status = Status::Delivered;
+                delivery.delivered_at = Some(now);
+                delivery.last_error = None;
+                delivered += 1;
+            }
+            Err(err) => {
+                log::debug!("Webhook {} to {} failed: {err}", delivery.id, delivery.url);
+                delivery.last_error = Some(err.to_string());
+
+                if delivery.attempts >= webhooks.max_attempts {
+                    delivery.status = Status::Failed;
+                    failed += 1;
+                } else {
+                    delivery.next_at = now + backoff(delivery.attempts);
+                }
+            }
+        }
+
+        queue.save_delivery(&delivery).await?;
+    }
+
+    Ok((delivered, failed))
+}
+
+async fn send(client: &reqwest::Client, endpoint: &Endpoint, delivery: &Delivery) -> Result<(), Error> {
+    let timestamp = chrono::Utc::now().timestamp();
+
+    let response = client
+        .post(&endpoint.url)
+        .header("content-type", "application/json")
+        .header("x-webx-event", delivery.event.to_string())
+        .header("x-webx-delivery", &delivery.id)
+        .header("x-webx-timestamp", timestamp)
+        .header("x-webx-signature", format!("sha256={}", sign(&endpoint.secret, timestamp, &delivery.payload)))
+        .body(delivery.payload.clone())
+        .send()
+        .await?;
+
+    match response.status().is_success() {
+        true => Ok(()),
+        false => Err(anyhow!("endpoint responded with {}", response.status())),
+    }
+}
+
+// 30s, 1m, 2m, ... capped at six hours
+fn backoff(attempts: u32) -> i64 { (30i64 << attempts.saturating_sub(1).min(16)).min(MAX_BACKOFF) }
+
+/// HMAC-SHA256 over `{timestamp}.{payload}`, hex encoded.
+pub(crate) fn sign(secret: &str, timestamp: i64, payload: &str) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC accepts keys of any length");
+    mac.update(format!("{timestamp}.{payload}").as_bytes());
+    hex::encode(mac.finalize().into_bytes())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::Embedded;
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::TcpListener,
+    };
+
+    fn store(name: &str) -> (Embedded, String) {
+        let path = std::env::temp_dir().join(format!("webx-webhook-{name}-{}", std::process::id())).to_string_lossy().into_owned();
+        (Embedded::open(&path).unwrap(), path)
+    }
+
+    fn delivery(url: &str) -> Delivery {
+        Delivery {
+            id: "d1".into(),
+            url: url.into(),
+            event: Event::Create,
+            domain: "site.it".into(),
+            payload: r#"{"event":"create"}"#.into(),
+            status: Status::Pending,
+            attempts: 0,
+            created_at: 0,
+            next_at: 0,
+            delivered_at: None,
+            last_error: None,
+        }
+    }
+
+    fn webhooks(url: &str, max_attempts: u32) -> Webhooks {
+        Webhooks {
+            endpoints: vec![Endpoint { url: url.into(), secret: "secret".into(), events: all_events() }],
+            max_attempts,
+            ..Default::default()
+        }
+    }
+
+    fn client() -> reqwest::Client { reqwest::Client::builder().no_proxy().build().unwrap() }
+
+    // answers one request with 200 and hands back its text
+    async fn endpoint() -> (String, tokio::task::JoinHandle<String>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/hook", listener.local_addr().unwrap());
+
+        let request = tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut request = vec![];
+            let mut buffer = [0u8; 4096];
+            while !String::from_utf8_lossy(&request).ends_with(r#"{"event":"create"}"#) {
+                let read = stream.read(&mut buffer).await.unwrap();
+                if read == 0 {
+                    break;
+                }
+                request.extend_from_slice(&buffer[..read]);
+            }
+            stream.write_all(b"HTTP/1.1 200 OK\r\ncontent-length: 0\r\n\r\n").await.unwrap();
+            String::from_utf8(request).unwrap()
+        });
+
+        (url, request)
+    }
+
+    fn header<'a>(request: &'a str, name: &str) -> &'a str {
+        request.lines().find_map(|line| line.strip_prefix(&format!("{name}: "))).unwrap()
+    }
+
+    #[test]
+    fn signatures_are_hex_hmacs_of_timestamp_and_payload() {
+        assert_eq!(sign("secret", 1700000000, r#"{"event":"create"}"#), "4fb99875e3a0fb562c3b0cd271bbe4c9c9ac46f0847f122bffa183484be87e63");
+        assert_ne!(sign("secret", 1700000001, r#"{"event":"create"}"#), sign("secret", 1700000000, r#"{"event":"create"}"#));
+    }
+
+    #[test]
+    fn backoff_doubles_up_to_the_cap() {
+        assert_eq!([1, 2, 3, 4].map(backoff), [30, 60, 120, 240]);
+        assert_eq!(backoff(0), 30);
+        assert_eq!(backoff(11), MAX_BACKOFF);
+        assert_eq!(backoff(u32::MAX), MAX_BACKOFF);
+    }
+
+    #[actix_web::test]
+    async fn delivered_requests_carry_a_valid_signature() {
+        let (queue, path) = store("delivered");
+        let (url, request) = endpoint().await;
+        queue.save_delivery(&delivery(&url)).await.unwrap();
+
+        let counts = flush(&queue, &client(), &webhooks(&url, 3)).await.unwrap();
+        let request = request.await.unwrap();
+        let saved = queue.find_delivery("d1").await.unwrap().unwrap();
+        std::fs::remove_dir_all(&path).unwrap();
+
+        let timestamp: i64 = header(&request, "x-webx-timestamp").parse().unwrap();
+        assert_eq!(counts, (1, 0));
+        assert_eq!(header(&request, "x-webx-event"), "create");
+        assert_eq!(header(&request, "x-webx-signature"), format!("sha256={}", sign("secret", timestamp, r#"{"event":"create"}"#)));
+        assert_eq!((saved.status, saved.attempts, saved.last_error), (Status::Delivered, 1, None));
+    }
+
+    #[actix_web::test]
+    async fn failures_are_retried_until_max_attempts() {
+        // nothing listens on port 1, so every attempt is refused
+        let url = "http://127.0.0.1:1/hook";
+        let (queue, path) = store("failed");
+        queue.save_delivery(&delivery(url)).await.unwrap();
+
+        let first = flush(&queue, &client(), &webhooks(url, 2)).await.unwrap();
+        let retrying = queue.find_delivery("d1").await.unwrap().unwrap();
+        let not_due = flush(&queue, &client(), &webhooks(url, 2)).await.unwrap();
+
+        queue.save_delivery(&Delivery { next_at: 0, ..retrying.clone() }).await.unwrap();
+        let last = flush(&queue, &client(), &webhooks(url, 2)).await.unwrap();
+        let failed = queue.find_delivery("d1").await.unwrap().unwrap();
+        std::fs::remove_dir_all(&path).unwrap();
+
+        assert_eq!(first, (0, 0));
+        assert_eq!((retrying.status, retrying.attempts), (Status::Pending, 1));
+        assert!(retrying.next_at - chrono::Utc::now().timestamp() > 20);
+        assert!(retrying.last_error.is_some());
+        assert_eq!(not_due, (0, 0));
+        assert_eq!(last, (0, 1));
+        assert_eq!((failed.status, failed.attempts), (Status::Failed, 2));
+    }
+}
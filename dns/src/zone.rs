@@ -0,0 +1,185 @@
+use crate::{
+    config::Config,
+    http::{Domain, RecordKind},
+    store::{ChangeStore, DomainStore},
+};
+use anyhow::Error;
+use std::fmt::Write;
+
+/// The SOA serial of every zone, both exported and served: the latest change sequence number. Serials compare with
+/// RFC 1982 arithmetic, so once the sequence outgrows 32 bits it wraps around.
+pub(crate) fn serial(seq: u64) -> u32 { u32::try_from(seq).unwrap_or_else(|_| (seq % (1 << 32)) as u32) }
+
+pub(crate) async fn export(db: &dyn DomainStore, changes: &dyn ChangeStore, config: &Config, tld: &str, serial: Option<u32>) -> Result<String, Error> {
+    let dns = &config.server.dns;
+    let now = chrono::Utc::now().timestamp();
+    let serial = match serial {
+        Some(serial) => serial,
+        None => self::serial(changes.last_seq().await?),
+    };
+    let primary = dns.nameservers.first().map(|ns| absolute(ns)).unwrap_or_else(|| format!("{tld}."));
+    let mut zone = String::new();
+
+    writeln!(zone, "; {tld} zone exported from webxDNS at {}", chrono::Utc::now().to_rfc3339())?;
+    writeln!(zone, "$ORIGIN {tld}.")?;
+    writeln!(zone, "$TTL {}", dns.ttl)?;
+    writeln!(zone, "@\tIN\tSOA\t{primary} hostmaster.{tld}. ({serial} 3600 600 86400 {})", dns.ttl)?;
+
+    for nameserver in &dns.nameservers {
+        writeln!(zone, "@\tIN\tNS\t{}", absolute(nameserver))?;
+    }
+
+    let mut skip = 0;
+    loop {
+        let page = db.search("", Some(tld), skip, 100).await?;
+        if page.is_empty() {
+            break;
+        }
+        skip += page.len() as u64;
+
+        for domain in page.iter().filter(|domain| domain.is_active(now)) {
+            write_domain(&mut zone, &domain.name, domain)?;
+
+            for subdomain in &domain.subdomains {
+                if let Some(view) = domain.subdomain(&subdomain.name) {
+                    write_domain(&mut zone, &view.name, &view)?;
+                }
+            }
+        }
+    }
+
+    Ok(zone)
+}
+
+// A CNAME may not share its owner with other data, so URL-only domains get nothing else.
+fn write_domain(zone: &mut String, owner: &str, domain: &Domain) -> Result<(), Error> {
+    let records = domain.records();
+    let has_address = records.iter().any(|record| matches!(record.kind, RecordKind::A | RecordKind::AAAA));
+
+    if !has_address {
+        let target = records.iter().find(|record| record.kind == RecordKind::URL);
+        let host = target.and_then(|record| url::Url::parse(&record.value).ok()).and_then(|url| url.host_str().map(absolute));
+
+        if let (Some(record), Some(host)) = (target, host) {
+            writeln!(zone, "{owner}\t{}\tIN\tCNAME\t{host}", record.ttl)?;
+            return Ok(());
+        }
+    }
+
+    for record in &records {
+        match record.kind {
+            RecordKind::A => writeln!(zone, "{owner}\t{}\tIN\tA\t{}", record.ttl, record.value)?,
+            RecordKind::AAAA => writeln!(zone, "{owner}\t{}\tIN\tAAAA\t{}", record.ttl, record.value)?,
+            RecordKind::TXT | RecordKind::URL => writeln!(zone, "{owner}\t{}\tIN\tTXT\t{}", record.ttl, quote(&record.value))?,
+            RecordKind::MX => writeln!(zone, "{owner}\t{}\tIN\tMX\t{} {}", record.ttl, record.priority.unwrap_or(10), absolute(&record.value))?,
+        }
+    }
+
+    Ok(())
+}
+
+fn absolute(host: &str) -> String { format!("{}.", host.trim_end_matches('.')) }
+
+fn quote(value: &str) -> String {
+    let mut chunks = vec![String::new()];
+
+    for c in value.chars() {
+        if chunks.last().is_some_and(|chunk| chunk.len() + c.len_utf8() > 255) {
+            chunks.push(String::new());
+        }
+        if let Some(chunk) = chunks.last_mut() {
+            chunk.push(c);
+        }
+    }
+
+    chunks.iter().map(|chunk| format!("\"{}\"", chunk.replace('\\', "\\\\").replace('"', "\\\""))).collect::<Vec<_>>().join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        http::{Ban, DomainRecord, Subdomain},
+        store::Embedded,
+    };
+
+    fn record(kind: RecordKind, value: &str) -> DomainRecord {
+        DomainRecord {
+            kind,
+            value: value.into(),
+            ..DomainRecord::primary("1.1.1.1")
+        }
+    }
+
+    fn domain(name: &str, records: Vec<DomainRecord>) -> Domain { Domain { name: name.into(), tld: "it".into(), records, ..Default::default() } }
+
+    fn lines(domain: &Domain) -> Vec<String> {
+        let mut zone = String::new();
+        write_domain(&mut zone, &domain.name, domain).unwrap();
+        zone.lines().map(String::from).collect()
+    }
+
+    #[test]
+    fn url_only_domains_are_a_cname_alone() {
+        let site = domain("site", vec![DomainRecord::primary("https://example.com/home"), record(RecordKind::TXT, "hello")]);
+        let ttl = site.records[0].ttl;
+
+        assert_eq!(lines(&site), [format!("site\t{ttl}\tIN\tCNAME\texample.com.")]);
+    }
+
+    #[test]
+    fn addresses_keep_every_record() {
+        let mut mx = record(RecordKind::MX, "mail.example.com");
+        mx.priority = Some(5);
+        let bank = domain("bank", vec![DomainRecord::primary("1.1.1.1"), DomainRecord::primary("https://example.com"), mx]);
+        let ttl = bank.records[0].ttl;
+
+        assert_eq!(
+            lines(&bank),
+            [
+                format!("bank\t{ttl}\tIN\tA\t1.1.1.1"),
+                format!("bank\t{ttl}\tIN\tTXT\t\"https://example.com\""),
+                format!("bank\t{ttl}\tIN\tMX\t5 mail.example.com."),
+            ]
+        );
+    }
+
+    #[test]
+    fn quoted_strings_are_escaped_and_split_into_255_byte_chunks() {
+        assert_eq!(quote(r#"say "hi" \o/"#), r#""say \"hi\" \\o/""#);
+
+        let long = format!("{}é{}", "a".repeat(254), "b".repeat(300));
+        let quoted = quote(&long);
+        let chunks: Vec<&str> = quoted.split("\" \"").collect();
+        assert_eq!(chunks.len(), 3);
+        // the two byte é does not fit after 254 bytes, so it starts the next chunk
+        assert_eq!(chunks[0], format!("\"{}", "a".repeat(254)));
+        assert!(chunks[1].starts_with('é'));
+        assert_eq!(chunks[1].len(), 255);
+    }
+
+    #[actix_web::test]
+    async fn exports_skip_inactive_domains_and_include_subdomains() {
+        let path = std::env::temp_dir().join(format!("webx-zone-{}", std::process::id())).to_string_lossy().into_owned();
+        let store = Embedded::open(&path).unwrap();
+        let mut bank = domain("bank", vec![DomainRecord::primary("1.1.1.1")]);
+        bank.subdomains = vec![Subdomain { name: "api".into(), ip: "2.2.2.2".into() }];
+        let banned = Domain { banned: Some(Ban { reason: None, at: 0 }), ..domain("shop", vec![DomainRecord::primary("3.3.3.3")]) };
+        let expired = Domain { expires_at: Some(1), ..domain("old", vec![DomainRecord::primary("4.4.4.4")]) };
+        for domain in [&bank, &banned, &expired] {
+            store.insert(domain).await.unwrap();
+        }
+        let mut config = Config::new();
+        config.server.dns.nameservers = vec!["ns1.example.com".into()];
+
+        let zone = export(&store, &store, &config, "it", Some(42)).await.unwrap();
+        std::fs::remove_dir_all(&path).unwrap();
+
+        assert!(zone.contains("$ORIGIN it.\n"));
+        assert!(zone.contains("@\tIN\tSOA\tns1.example.com. hostmaster.it. (42 3600 600 86400 300)\n"));
+        assert!(zone.contains("@\tIN\tNS\tns1.example.com.\n"));
+        assert!(zone.contains("\tIN\tA\t1.1.1.1\n"));
+        assert!(zone.contains("api.bank\t"));
+        assert!(!zone.contains("3.3.3.3") && !zone.contains("4.4.4.4"));
+    }
+}
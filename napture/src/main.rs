@@ -60,6 +60,7 @@ use historymod::imap_login_with_creds;
 use std::net::UdpSocket;
 use historymod::compute_sha1;
 use globals::APPDATA_PATH;
+use globals::DNS_KEYS;
 use globals::DNS_SERVER;
 use globals::LUA_TIMEOUTS;
 use globals::LUA_LOGS;
@@ -68,6 +69,8 @@ use gtk::gdk::Display;
 use gtk::gio;
 use gtk::CssProvider;
 use serde::Deserialize;
+use base64::prelude::*;
+use ed25519_dalek::{Signature, VerifyingKey};
 use std::net::TcpStream;
 use std::io::Read;
 use gtk::prelude::*;
@@ -119,6 +122,12 @@ fn main() -> glib::ExitCode {
         search image {
             margin-right: 5px;
         }
+        search.verified {
+            box-shadow: inset 0 0 0 1px #2ea043;
+        }
+        search.refused {
+            box-shadow: inset 0 0 0 1px #da3633;
+        }
         "
         .to_string();
 
@@ -172,7 +181,16 @@ fn handle_search_update(
     let searchbar_mut = searchbar_clone.borrow_mut();
 
     let url = searchbar_mut.text().to_string();
-    let dns_url = fetch_dns(url.clone());
+    let (dns_url, verified) = match fetch_dns(url.clone()) {
+        Ok(answer) => answer,
+        Err(warning) => {
+            // never navigate to an answer that failed verification
+            searchbar_mut.set_text(&url.replace("buss://", ""));
+            show_refused(&searchbar_mut, &warning);
+            tab_in_closure.label_widget.set_label("Untrusted DNS answer");
+            return;
+        }
+    };
 
     if dns_url.is_empty() {
         tab_in_closure.url = url.clone();
@@ -182,6 +200,7 @@ fn handle_search_update(
 
     searchbar_mut.set_text(&url.replace("buss://", ""));
     searchbar_mut.set_position(-1);
+    show_verified(&searchbar_mut, verified);
 
     if let Some(root) = searchbar_mut.root() {
         root.set_focus(None as Option<&gtk::Widget>)
@@ -235,7 +254,8 @@ fn build_ui(app: &adw::Application, args: Rc<RefCell<Vec<String>>>, config: Rc<R
         dev_build.to_string()
     } else { DEFAULT_URL.to_string() };
 
-    let default_dns_url = fetch_dns(default_url.clone());
+    let default_dns = fetch_dns(default_url.clone());
+    let (default_dns_url, default_verified) = default_dns.clone().unwrap_or_default();
 
     let default_tab_url = if default_dns_url.is_empty() {
         default_url.clone()
@@ -254,6 +274,10 @@ fn build_ui(app: &adw::Application, args: Rc<RefCell<Vec<String>>>, config: Rc<R
         .width_request(500)
         .text(default_url.clone())
         .build();
+    match &default_dns {
+        Ok(_) => show_verified(&search, default_verified),
+        Err(warning) => show_refused(&search, warning),
+    }
     let empty_label = gtk::Label::new(Some(""));
     let headerbar = gtk::HeaderBar::builder().build();
 
@@ -367,6 +391,10 @@ fn build_ui(app: &adw::Application, args: Rc<RefCell<Vec<String>>>, config: Rc<R
         *DNS_SERVER.lock().unwrap() = dns.to_string();
     }
 
+    if let Ok(keys) = serde_json::from_value(config.borrow()["dns_keys"].clone()) {
+        *DNS_KEYS.lock().unwrap() = keys;
+    }
+
     if let Ok((htmlview, provider)) =
         b9::html::build_ui(tab1.clone(), None, rc_scroll.clone(), rc_search.clone())
     {
@@ -647,40 +675,74 @@ fn make_go_forward_button() -> gtk::Button {
 
 #[derive(Deserialize)]
 struct DomainInfo {
+    name: String,
+    tld: String,
     ip: String,
+    #[serde(default)]
+    signed_at: Option<i64>,
+    #[serde(default)]
+    verified: bool,
+}
+
+#[derive(Deserialize)]
+struct SigningKey {
+    public_key: String,
 }
 
-fn fetch_dns(url: String) -> String {
+// how old a signed answer may be before it is treated as a replay
+const SIGNATURE_MAX_AGE: i64 = 300;
+
+// Returns the resolved URL, and whether the registry verified that the domain owner controls it.
+// Answers that fail signature verification are refused with the reason.
+fn fetch_dns(url: String) -> Result<(String, bool), String> {
     let mut url = url.replace("buss://", "");
 
     url = url.split("?").nth(0).unwrap_or(&url).to_owned();
 
     let client: reqwest::blocking::ClientBuilder = reqwest::blocking::Client::builder();
 
-    let clienturl = format!(
-        "{}/domain/{}/{}",
-        DNS_SERVER.lock().unwrap().as_str(),
-        url.split('.').next().unwrap_or(""),
-        url.split('.').nth(1).unwrap_or("")
-            .split('/').next().unwrap_or(""),
-    );
+    let host = url.split('/').next().unwrap_or("");
+    let host = idna::domain_to_ascii(host).unwrap_or_else(|_| host.to_lowercase());
+    let (name, tld) = host.rsplit_once('.').unwrap_or((&host, ""));
+
+    let dns_server = DNS_SERVER.lock().unwrap().clone();
+    let clienturl = format!("{}/domain/{}/{}", dns_server, name, tld);
 
     
     let client = match client.build() {
         Ok(client) => client,
         Err(e) => {
             eprintln!("ERROR: Couldn't build reqwest client: {}", e);
-            return url;
+            return Ok((url, false));
         }
     };
 
     if let Ok(response) = client.get(clienturl).send() {
         let status = response.status();
+        let signature = response
+            .headers()
+            .get("x-webx-signature")
+            .and_then(|value| value.to_str().ok())
+            .map(str::to_owned);
+        let body = response.bytes().unwrap_or_default();
+
+        if let Ok(json) = serde_json::from_slice::<DomainInfo>(&body) {
+            if let Err(warning) = verify_dns(&client, &dns_server, &body, signature.as_deref(), json.signed_at) {
+                let warning = format!("{} ({})", warning, host);
+                lualog!("warning", warning.clone());
+                return Err(warning);
+            }
+
+            // a valid signature only covers the body, so make sure it answers the name that was asked for
+            if !json.name.eq_ignore_ascii_case(name) || !json.tld.eq_ignore_ascii_case(tld) {
+                let warning = format!("The DNS server answered for {}.{} instead of {}", json.name, json.tld, host);
+                lualog!("warning", warning.clone());
+                return Err(warning);
+            }
 
-        if let Ok(json) = response.json::<DomainInfo>() {
             let path = url.split_once('/')
                 .unwrap_or(("", "")).1;
-            json.ip + &format!("/{}", path)
+            Ok((json.ip + &format!("/{}", path), json.verified))
         } else {
             lualog!(
                 "debug",
@@ -689,14 +751,95 @@ fn fetch_dns(url: String) -> String {
                     status.as_u16()
                 )
             );
-            String::new()
+            Ok((String::new(), false))
         }
     } else {
         lualog!("debug", "Failed to send HTTP request to DNS API.");
-        String::new()
+        Ok((String::new(), false))
+    }
+}
+
+fn show_verified(searchbar: &gtk::SearchEntry, verified: bool) {
+    searchbar.remove_css_class("refused");
+
+    if verified {
+        searchbar.add_css_class("verified");
+        searchbar.set_tooltip_text(Some("The registry verified that this domain's owner controls the site it points to."));
+    } else {
+        searchbar.remove_css_class("verified");
+        searchbar.set_tooltip_text(None);
+    }
+}
+
+fn show_refused(searchbar: &gtk::SearchEntry, warning: &str) {
+    searchbar.remove_css_class("verified");
+    searchbar.add_css_class("refused");
+    searchbar.set_tooltip_text(Some(&format!("Refused the DNS answer: {}", warning)));
+}
+
+// Checks a DNS answer against the key pinned for the server. Servers that sign but have no pin yet are pinned on first use.
+fn verify_dns(
+    client: &reqwest::blocking::Client,
+    server: &str,
+    body: &[u8],
+    signature: Option<&str>,
+    signed_at: Option<i64>,
+) -> Result<(), String> {
+    let pinned = DNS_KEYS.lock().unwrap().get(server).cloned();
+
+    let key = match (pinned, signature) {
+        (Some(key), _) => key,
+        (None, Some(_)) => pin_dns_key(client, server)?,
+        (None, None) => return Ok(()),
+    };
+
+    let Some(signature) = signature else {
+        return Err(format!("DNS server {} sent an unsigned answer, but a signing key is pinned for it", server));
+    };
+
+    let key = BASE64_STANDARD
+        .decode(key)
+        .ok()
+        .and_then(|key| <[u8; 32]>::try_from(key).ok())
+        .and_then(|key| VerifyingKey::from_bytes(&key).ok())
+        .ok_or(format!("The key pinned for DNS server {} is not a valid Ed25519 key", server))?;
+
+    let signature = signature
+        .strip_prefix("ed25519=")
+        .and_then(|signature| BASE64_STANDARD.decode(signature).ok())
+        .and_then(|signature| Signature::from_slice(&signature).ok())
+        .ok_or(format!("DNS server {} sent a malformed signature", server))?;
+
+    if key.verify_strict(body, &signature).is_err() {
+        return Err(format!("DNS answer does not match the key pinned for {}, it may have been tampered with", server));
+    }
+
+    match signed_at {
+        Some(at) if (chrono::Utc::now().timestamp() - at).abs() <= SIGNATURE_MAX_AGE => Ok(()),
+        _ => Err(format!("DNS server {} sent an outdated signed answer", server)),
     }
 }
 
+fn pin_dns_key(client: &reqwest::blocking::Client, server: &str) -> Result<String, String> {
+    let key = client
+        .get(format!("{}/.well-known/webx-signing-key", server))
+        .send()
+        .and_then(|response| response.error_for_status())
+        .and_then(|response| response.json::<SigningKey>())
+        .map_err(|e| format!("Couldn't fetch the signing key of DNS server {}: {}", server, e))?
+        .public_key;
+
+    let keys = {
+        let mut keys = DNS_KEYS.lock().unwrap();
+        keys.insert(server.to_string(), key.clone());
+        serde_json::to_value(&*keys).unwrap_or_default()
+    };
+    set_config(String::from("dns_keys"), keys, false);
+    lualog!("debug", format!("Pinned signing key of DNS server {}.", server));
+
+    Ok(key)
+}
+
 async fn display_lua_logs(app: &Rc<RefCell<adw::Application>>) {
     let window: Window = Object::builder()
         .property("application", glib::Value::from(&*app.borrow_mut()))